@@ -0,0 +1,84 @@
+//! Throughput of `apply_keystream` across message sizes and positions, one byte at a time, and
+//! of `get_keystream`.
+//!
+//! Run with `cargo bench`. The last block computed is cached, so long messages and runs of small
+//! reads compute about one ChaCha block per 64 bytes of keystream at any position.
+
+use chacha20::ChaCha20;
+use std::time::Instant;
+
+/// Bytes of keystream produced per measurement, whatever the message size.
+const TOTAL_LEN: usize = 20 << 20;
+const SIZES: [(&str, usize); 4] = [
+    ("64 B", 64),
+    ("1 KiB", 1 << 10),
+    ("64 KiB", 64 << 10),
+    ("1 MiB", 1 << 20),
+];
+
+fn megabytes_per_second(bytes: usize, secs: f64) -> f64 {
+    bytes as f64 / secs / 1_000_000.0
+}
+
+fn apply_keystream(len: usize, seek: u64) -> f64 {
+    let key = [0x42; 32];
+    let nonce = [0x24; 12];
+    let mut buffer = vec![0u8; len];
+    let rounds = TOTAL_LEN / len;
+    let start = Instant::now();
+    for _ in 0..rounds {
+        let mut chacha = ChaCha20::new(key, nonce, seek);
+        chacha.apply_keystream(&mut buffer);
+    }
+    megabytes_per_second(len * rounds, start.elapsed().as_secs_f64())
+}
+
+/// Bytes of keystream produced by the byte-at-a-time measurement.
+const BYTEWISE_LEN: usize = 1 << 20;
+
+fn apply_keystream_bytewise() -> f64 {
+    let mut chacha = ChaCha20::new([0x42; 32], [0x24; 12], 0);
+    let mut byte = [0u8; 1];
+    let start = Instant::now();
+    for _ in 0..BYTEWISE_LEN {
+        chacha.apply_keystream(&mut byte);
+    }
+    megabytes_per_second(BYTEWISE_LEN, start.elapsed().as_secs_f64())
+}
+
+/// Also returns a byte folded from every block, so the blocks cannot be optimised away.
+fn get_keystream() -> (f64, u8) {
+    let mut chacha = ChaCha20::new([0x42; 32], [0x24; 12], 0);
+    let blocks = (TOTAL_LEN / 64) as u32;
+    let mut sink = 0u8;
+    let start = Instant::now();
+    for block in 0..blocks {
+        sink ^= chacha.get_keystream(block)[0];
+    }
+    let secs = start.elapsed().as_secs_f64();
+    (megabytes_per_second(TOTAL_LEN, secs), sink)
+}
+
+fn main() {
+    for (name, len) in SIZES {
+        println!(
+            "apply_keystream {:>6}, aligned (seek 0):   {:>8.1} MB/s",
+            name,
+            apply_keystream(len, 0)
+        );
+        println!(
+            "apply_keystream {:>6}, unaligned (seek 1): {:>8.1} MB/s",
+            name,
+            apply_keystream(len, 1)
+        );
+    }
+    println!(
+        "apply_keystream    1 B at a time:          {:>8.1} MB/s",
+        apply_keystream_bytewise()
+    );
+    let (rate, checksum) = get_keystream();
+    println!(
+        "get_keystream:                             {:>8.1} MB/s (checksum {})",
+        rate, checksum
+    );
+}
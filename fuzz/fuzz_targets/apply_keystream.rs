@@ -0,0 +1,35 @@
+//! Differential fuzzing of `apply_keystream` against the RustCrypto crate.
+//!
+//! Run with `cargo fuzz run apply_keystream` from the repository root. The input is read as a
+//! 32-byte key, a 12-byte nonce, an 8-byte little-endian seek and the message.
+#![no_main]
+
+use chacha20::ChaCha20;
+use libfuzzer_sys::fuzz_target;
+use rustcrypto_chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+
+/// The reference crate stops before the last block of the 32-bit counter.
+const LAST_SEEK: u64 = (u32::MAX as u64 - 1) * 64;
+
+fn differential(data: &[u8]) {
+    if data.len() < 52 {
+        return;
+    }
+    let (key, rest) = data.split_at(32);
+    let (nonce, rest) = rest.split_at(12);
+    let (seek, message) = rest.split_at(8);
+    let key: [u8; 32] = key.try_into().unwrap();
+    let nonce: [u8; 12] = nonce.try_into().unwrap();
+    let seek_limit = LAST_SEEK.saturating_sub(message.len() as u64).max(1);
+    let seek = u64::from_le_bytes(seek.try_into().unwrap()) % seek_limit;
+
+    let mut ours = message.to_vec();
+    ChaCha20::new(key, nonce, seek).apply_keystream(&mut ours);
+    let mut theirs = message.to_vec();
+    let mut cipher = rustcrypto_chacha20::ChaCha20::new(&key.into(), &nonce.into());
+    cipher.seek(seek);
+    cipher.apply_keystream(&mut theirs);
+    assert_eq!(ours, theirs);
+}
+
+fuzz_target!(|data: &[u8]| differential(data));
@@ -0,0 +1,1301 @@
+//! The ChaCha20 stream cipher and its keystream.
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+use crate::core::{absorb, chacha_block, hchacha20, prepare_state};
+use crate::error::{ChaChaError, WeakInput};
+use crate::{CHACHA_BLOCKSIZE, KEYSTREAM_LEN};
+
+pub(crate) const RFC_TEST_KEY: [u8; 32] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25,
+    26, 27, 28, 29, 30, 31,
+];
+
+/// The RFC 7539 section 2.4.2 sunscreen plaintext.
+const RFC_SUNSCREEN: &[u8; 114] = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+
+/// The RFC 7539 section 2.4.2 ciphertext of [`RFC_SUNSCREEN`], at block 1 under [`RFC_TEST_KEY`].
+const RFC_SUNSCREEN_CIPHERTEXT: [u8; 114] = [
+    0x6e, 0x2e, 0x35, 0x9a, 0x25, 0x68, 0xf9, 0x80, 0x41, 0xba, 0x07, 0x28, 0xdd, 0x0d, 0x69, 0x81,
+    0xe9, 0x7e, 0x7a, 0xec, 0x1d, 0x43, 0x60, 0xc2, 0x0a, 0x27, 0xaf, 0xcc, 0xfd, 0x9f, 0xae, 0x0b,
+    0xf9, 0x1b, 0x65, 0xc5, 0x52, 0x47, 0x33, 0xab, 0x8f, 0x59, 0x3d, 0xab, 0xcd, 0x62, 0xb3, 0x57,
+    0x16, 0x39, 0xd6, 0x24, 0xe6, 0x51, 0x52, 0xab, 0x8f, 0x53, 0x0c, 0x35, 0x9f, 0x08, 0x61, 0xd8,
+    0x07, 0xca, 0x0d, 0xbf, 0x50, 0x0d, 0x6a, 0x61, 0x56, 0xa3, 0x8e, 0x08, 0x8a, 0x22, 0xb6, 0x5e,
+    0x52, 0xbc, 0x51, 0x4d, 0x16, 0xcc, 0xf8, 0x06, 0x81, 0x8c, 0xe9, 0x1a, 0xb7, 0x79, 0x37, 0x36,
+    0x5a, 0xf9, 0x0b, 0xbf, 0x74, 0xa3, 0x5b, 0xe6, 0xb4, 0x0b, 0x8e, 0xed, 0xf2, 0x78, 0x5e, 0x42,
+    0x87, 0x4d,
+];
+
+/// Approximate scratch, in bytes, used to produce one block of keystream.
+///
+/// Covers the prepared state, the two serialized blocks the block at the current seek is
+/// assembled from and the 64 bytes returned. Function call overhead is not included.
+pub const STACK_USAGE_PER_BLOCK: usize = 16 * 4 + 2 * CHACHA_BLOCKSIZE + CHACHA_BLOCKSIZE;
+
+/// Estimate the peak scratch, in bytes, used by [`ChaCha20::apply_keystream`] on a message of `len` bytes.
+///
+/// Blocks are produced one at a time, so the estimate does not grow with the message length.
+pub const fn apply_keystream_stack_usage(len: usize) -> usize {
+    if len == 0 {
+        0
+    } else {
+        STACK_USAGE_PER_BLOCK
+    }
+}
+
+/// Build a per-message nonce from a 4-byte `salt` and a message `counter`.
+///
+/// The salt fills the first 4 bytes and the little-endian counter the last 8, the layout WireGuard
+/// uses with a zero salt. A counter must never repeat under the same key and salt.
+///
+/// There is no AEAD type in this crate yet, so the nonce is for use with [`ChaCha20`] directly.
+pub const fn nonce_from_counter(salt: [u8; 4], counter: u64) -> [u8; 12] {
+    let c = counter.to_le_bytes();
+    [
+        salt[0], salt[1], salt[2], salt[3], c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7],
+    ]
+}
+
+/// Draw a fresh 12-byte nonce from the platform CSPRNG.
+#[cfg(feature = "getrandom")]
+pub fn random_nonce() -> Result<[u8; 12], getrandom::Error> {
+    let mut nonce = [0u8; 12];
+    getrandom::getrandom(&mut nonce)?;
+    Ok(nonce)
+}
+
+/// Derive a 32-byte subkey from `master` with HChaCha20, using `context` as the 16-byte nonce.
+///
+/// Each distinct `context` gives an independent subkey, so one master key can serve several purposes.
+pub fn derive_subkey(master: [u8; 32], context: [u8; 16]) -> [u8; 32] {
+    hchacha20(master, context)
+}
+
+/// Fill `out` with the keystream starting at byte `start`, without keeping a cipher around.
+///
+/// # Panics
+///
+/// Panics if `start` is past the end of the keystream, or if `start + out.len()` would run past it.
+pub fn keystream_range(key: [u8; 32], nonce: [u8; 12], start: u64, out: &mut [u8]) {
+    KeyStream::new(key, nonce, start).fill(out);
+}
+
+/// The 64-byte block of keystream at block `counter`: one run of the block function, serialized.
+///
+/// ```rust
+/// let key: [u8; 32] = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+///     .unwrap()
+///     .try_into()
+///     .unwrap();
+/// let nonce: [u8; 12] = hex::decode("000000090000004a00000000").unwrap().try_into().unwrap();
+/// let block = chacha20::keystream_block(key, nonce, 1);
+/// assert_eq!(
+///     hex::encode(block),
+///     "10f1e7e4d13b5915500fdd1fa32071c4c7d1f4c733c068030422aa9ac3d46c4e\
+///      d2826446079faa0914c2d705d98b02a2b5129cd1de164eb9cbd083e8a2503c4e"
+/// );
+/// ```
+pub fn keystream_block(key: [u8; 32], nonce: [u8; 12], counter: u32) -> [u8; 64] {
+    let mut state = prepare_state(key, nonce, counter);
+    chacha_block(&mut state);
+    keystream_from_state(&mut state, ByteOrder::Little)
+}
+
+/// Apply the keystream starting at block `counter` to `buf` in one call, without keeping a cipher around.
+pub fn chacha20_xor(key: [u8; 32], nonce: [u8; 12], counter: u32, buf: &mut [u8]) {
+    ChaCha20::new_from_block(key, nonce, counter).apply_keystream(buf);
+}
+
+/// XOR two 64-byte blocks, such as blocks of keystream, byte by byte.
+pub fn xor_blocks(a: &[u8; 64], b: &[u8; 64]) -> [u8; 64] {
+    let mut out = *a;
+    for (o, y) in out.iter_mut().zip(b.iter()) {
+        *o ^= *y;
+    }
+    out
+}
+
+/// A fast keyed checksum of `data`, for detecting accidental changes.
+///
+/// This is not a MAC: it has no security analysis and must not be used to authenticate messages.
+/// The data is mixed into successive ChaCha blocks starting from the first keystream block of `key`
+/// with an all-zero nonce, and the first half of the final state is returned.
+pub fn keyed_digest(key: [u8; 32], data: &[u8]) -> [u8; 32] {
+    let mut state = prepare_state(key, [0; 12], 0);
+    chacha_block(&mut state);
+    absorb(&mut state, data);
+    let mut digest = [0u8; 32];
+    for (chunk, word) in digest.chunks_exact_mut(4).zip(state.iter()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+/// A 32-byte ChaCha20 key, kept apart from other 32-byte values by its type.
+#[derive(Clone, Copy)]
+pub struct Key([u8; 32]);
+
+impl From<[u8; 32]> for Key {
+    fn from(key: [u8; 32]) -> Self {
+        Key(key)
+    }
+}
+
+impl From<Key> for [u8; 32] {
+    fn from(key: Key) -> Self {
+        key.0
+    }
+}
+
+impl AsRef<[u8]> for Key {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl ::core::fmt::Debug for Key {
+    /// Never shows the key bytes.
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        f.write_str("Key(<redacted>)")
+    }
+}
+
+/// A 12-byte ChaCha20 nonce, kept apart from other 12-byte values by its type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nonce([u8; 12]);
+
+impl From<[u8; 12]> for Nonce {
+    fn from(nonce: [u8; 12]) -> Self {
+        Nonce(nonce)
+    }
+}
+
+impl From<Nonce> for [u8; 12] {
+    fn from(nonce: Nonce) -> Self {
+        nonce.0
+    }
+}
+
+impl AsRef<[u8]> for Nonce {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// How the words of each block are serialized into keystream bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Little-endian words, as specified by RFC 7539.
+    Little,
+    /// Big-endian words. This is not ChaCha20 as specified, and is only for interoperating with
+    /// implementations that serialize the state this way.
+    Big,
+}
+
+/// The ChaCha20 keystream, for uses other than XOR-encryption such as deriving masks.
+pub struct KeyStream {
+    pub(crate) key: [u8; 32],
+    pub(crate) nonce: [u8; 12],
+    pub(crate) inner: u32,
+    pub(crate) seek: usize,
+    order: ByteOrder,
+    /// The counter and bytes of the last block computed, so reads within a block compute it once.
+    cache: Option<(u32, [u8; 64])>,
+    #[cfg(feature = "profiling")]
+    block_computations: u64,
+}
+
+impl KeyStream {
+    /// Make a new keystream from an index in the keystream.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `seek` is past the end of the keystream.
+    pub fn new(key: [u8; 32], nonce: [u8; 12], seek: u64) -> Self {
+        let mut stream = KeyStream::at(key, nonce, 0, 0);
+        stream.set_position(checked_seek(seek));
+        stream
+    }
+
+    /// Make a new keystream from a block in the keystream.
+    pub fn new_from_block(key: [u8; 32], nonce: [u8; 12], block: u32) -> Self {
+        KeyStream::at(key, nonce, block, 0)
+    }
+
+    const fn at(key: [u8; 32], nonce: [u8; 12], inner: u32, seek: usize) -> Self {
+        KeyStream {
+            key,
+            nonce,
+            inner,
+            seek,
+            order: ByteOrder::Little,
+            cache: None,
+            #[cfg(feature = "profiling")]
+            block_computations: 0,
+        }
+    }
+
+    /// The next 64 bytes of keystream, advancing the block counter by one.
+    pub fn next_block(&mut self) -> [u8; 64] {
+        let kstream = self.current();
+        self.advance(CHACHA_BLOCKSIZE);
+        kstream
+    }
+
+    /// Fill `out` with keystream, one block at a time.
+    ///
+    /// The output and the position afterwards match [`ChaCha20::apply_keystream`] over a zeroed buffer.
+    pub fn fill(&mut self, out: &mut [u8]) {
+        self.assert_remaining(out.len());
+        for chunk in out.chunks_mut(CHACHA_BLOCKSIZE) {
+            let kstream = self.window(chunk.len());
+            self.advance(chunk.len());
+            chunk.copy_from_slice(&kstream[..chunk.len()]);
+        }
+    }
+
+    /// The current index in the keystream.
+    pub fn position(&self) -> u64 {
+        self.inner as u64 * CHACHA_BLOCKSIZE as u64 + self.seek as u64
+    }
+
+    /// The keystream block starting at the current position, without advancing it.
+    pub(crate) fn current(&mut self) -> [u8; 64] {
+        self.window(CHACHA_BLOCKSIZE)
+    }
+
+    /// The next `len` bytes of keystream at the front of a block, without advancing.
+    ///
+    /// Bytes past `len` are unspecified, and bytes past the end of the keystream are zero. The
+    /// following block is only computed when the window reaches into it, and the last block
+    /// computed is cached, so byte-at-a-time reads compute each block once. The `constant-path`
+    /// feature bypasses the cache and always computes both.
+    pub(crate) fn window(&mut self, len: usize) -> [u8; 64] {
+        if cfg!(feature = "constant-path") {
+            #[cfg(feature = "profiling")]
+            {
+                self.block_computations += keystream_block_computations(self.seek);
+            }
+            return keystream_at_slice(self.key, self.nonce, self.inner, self.seek, self.order);
+        }
+        let first = self.block(self.inner);
+        if self.seek == 0 {
+            return first;
+        }
+        let mut kstream: [u8; 64] = [0; 64];
+        kstream[..CHACHA_BLOCKSIZE - self.seek].copy_from_slice(&first[self.seek..]);
+        if self.seek + len > CHACHA_BLOCKSIZE {
+            if let Some(next) = self.inner.checked_add(1) {
+                let second = self.block(next);
+                kstream[CHACHA_BLOCKSIZE - self.seek..].copy_from_slice(&second[..self.seek]);
+            }
+        }
+        kstream
+    }
+
+    /// The serialized block at counter `block`, from the cache when it holds that block.
+    fn block(&mut self, block: u32) -> [u8; 64] {
+        if let Some((cached, kstream)) = self.cache {
+            if cached == block {
+                return kstream;
+            }
+        }
+        #[cfg(feature = "profiling")]
+        {
+            self.block_computations += 1;
+        }
+        let mut state = prepare_state(self.key, self.nonce, block);
+        chacha_block(&mut state);
+        let kstream = keystream_from_state(&mut state, self.order);
+        self.cache = Some((block, kstream));
+        kstream
+    }
+
+    /// Move the position forward by `len` bytes, keeping the offset into a partly used block.
+    ///
+    /// Reaching the very end of the keystream leaves the position at the end of block `u32::MAX`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the position would pass the end of the keystream.
+    fn advance(&mut self, len: usize) {
+        let position = self.position() + len as u64;
+        assert!(
+            position <= KEYSTREAM_LEN,
+            "Keystream within the 32-bit block counter."
+        );
+        self.set_position(position);
+    }
+
+    /// Check that `len` more bytes of keystream exist, before any of them are produced.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the position would pass the end of the keystream.
+    pub(crate) fn assert_remaining(&self, len: usize) {
+        assert!(
+            len as u64 <= KEYSTREAM_LEN - self.position(),
+            "Keystream within the 32-bit block counter."
+        );
+    }
+
+    /// Move to byte `position`, which must not pass the end of the keystream.
+    ///
+    /// The very end is stored as offset 64 into block `u32::MAX`, as the block counter has no room
+    /// for the block after it.
+    pub(crate) fn set_position(&mut self, position: u64) {
+        debug_assert!(position <= KEYSTREAM_LEN);
+        if position == KEYSTREAM_LEN {
+            self.inner = u32::MAX;
+            self.seek = CHACHA_BLOCKSIZE;
+        } else {
+            self.inner = (position / CHACHA_BLOCKSIZE as u64) as u32;
+            self.seek = (position % CHACHA_BLOCKSIZE as u64) as usize;
+        }
+    }
+}
+
+impl PartialEq for KeyStream {
+    /// Compares the key in constant time, the nonce and position normally.
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(&self.key, &other.key)
+            && self.nonce == other.nonce
+            && self.inner == other.inner
+            && self.seek == other.seek
+            && self.order == other.order
+    }
+}
+
+impl Eq for KeyStream {}
+
+impl ::core::fmt::Debug for KeyStream {
+    /// Shows the nonce and position, but never the key.
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        let mut s = f.debug_struct("KeyStream");
+        s.field("key", &"<redacted>")
+            .field("nonce", &self.nonce)
+            .field("inner", &self.inner)
+            .field("seek", &self.seek)
+            .field("order", &self.order);
+        #[cfg(feature = "profiling")]
+        s.field("block_computations", &self.block_computations);
+        s.finish()
+    }
+}
+
+/// The ChaCha20 stream cipher.
+#[derive(Debug)]
+pub struct ChaCha20 {
+    pub(crate) stream: KeyStream,
+    start_inner: u32,
+    start_seek: usize,
+    /// The lowest and highest byte positions of keystream applied so far.
+    #[cfg(feature = "debug_guards")]
+    produced: Option<(u64, u64)>,
+}
+
+impl ChaCha20 {
+    /// The length of a key in bytes.
+    pub const KEY_SIZE: usize = 32;
+    /// The length of a nonce in bytes.
+    pub const NONCE_SIZE: usize = 12;
+    /// The length of a block of keystream in bytes.
+    pub const BLOCK_SIZE: usize = CHACHA_BLOCKSIZE;
+    /// The number of rounds of the block function, counting each column and diagonal round.
+    pub const ROUNDS: usize = 20;
+
+    /// Make a new instance of ChaCha20 from an index in the keystream.
+    ///
+    /// The key and nonce may be given as raw arrays or as [`Key`] and [`Nonce`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `seek` is past the end of the keystream.
+    pub fn new(key: impl Into<Key>, nonce: impl Into<Nonce>, seek: u64) -> Self {
+        ChaCha20::from_stream(KeyStream::new(key.into().0, nonce.into().0, seek))
+    }
+
+    /// Make a new instance of ChaCha20 from an index in the keystream that serializes each block
+    /// as big-endian words.
+    ///
+    /// This is not interoperable with RFC 7539 ChaCha20 and exists only to talk to peers that
+    /// serialize the state words in the wrong order. See [`ByteOrder::Big`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `seek` is past the end of the keystream.
+    pub fn new_be(key: [u8; 32], nonce: [u8; 12], seek: u64) -> Self {
+        let mut stream = KeyStream::new(key, nonce, seek);
+        stream.order = ByteOrder::Big;
+        ChaCha20::from_stream(stream)
+    }
+
+    /// Make a new instance of ChaCha20 from an index in the keystream, usable in `const` contexts.
+    pub const fn new_const(key: [u8; 32], nonce: [u8; 12], seek: u32) -> Self {
+        ChaCha20::from_stream(KeyStream::at(key, nonce, seek / 64, (seek % 64) as usize))
+    }
+
+    /// Make a new instance of ChaCha20 from an index in the keystream, rejecting inputs that look copied from documentation.
+    ///
+    /// This is a heuristic: the RFC 7539 test key and all-zero keys or nonces are valid ChaCha20 inputs,
+    /// but seeing them in production almost always means a test vector or an uninitialized buffer made
+    /// it into real code. Use [`ChaCha20::new`] where these inputs are intended, such as in tests.
+    pub fn new_production(key: [u8; 32], nonce: [u8; 12], seek: u64) -> Result<Self, WeakInput> {
+        if key == RFC_TEST_KEY {
+            return Err(WeakInput::TestVectorKey);
+        }
+        if key.iter().all(|&b| b == 0) {
+            return Err(WeakInput::ZeroKey);
+        }
+        if nonce.iter().all(|&b| b == 0) {
+            return Err(WeakInput::ZeroNonce);
+        }
+        Ok(ChaCha20::new(key, nonce, seek))
+    }
+
+    /// Make a new instance of ChaCha20 from an index in the keystream, rejecting an all-zero key.
+    ///
+    /// An all-zero key almost always means the key buffer was never filled. Unlike
+    /// [`ChaCha20::new_production`], test vector keys and all-zero nonces are accepted.
+    pub fn new_checked(key: [u8; 32], nonce: [u8; 12], seek: u64) -> Result<Self, WeakInput> {
+        if key.iter().all(|&b| b == 0) {
+            return Err(WeakInput::ZeroKey);
+        }
+        Ok(ChaCha20::new(key, nonce, seek))
+    }
+
+    /// Run the RFC 7539 section 2.4.2 encryption and check it against the published ciphertext.
+    ///
+    /// A power-on self-test for environments that require one. Returns whether the output matched.
+    pub fn self_test() -> bool {
+        let nonce = [0, 0, 0, 0, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+        let mut buffer = *RFC_SUNSCREEN;
+        ChaCha20::new_from_block(RFC_TEST_KEY, nonce, 1).apply_keystream(&mut buffer);
+        buffer == RFC_SUNSCREEN_CIPHERTEXT
+    }
+
+    /// Make a new instance of ChaCha20 from a block in the keystream.
+    ///
+    /// `new_from_block(key, nonce, block)` is equivalent to `new(key, nonce, block as u64 * 64)`
+    /// for every `block`, up to and including `u32::MAX`.
+    pub fn new_from_block(key: [u8; 32], nonce: [u8; 12], block: u32) -> Self {
+        ChaCha20::from_stream(KeyStream::new_from_block(key, nonce, block))
+    }
+
+    /// Make a new instance of ChaCha20 whose keystream is separated from others under the same key
+    /// by a 16-byte `personalization`.
+    ///
+    /// This is not standard ChaCha20. The cipher is keyed with the HChaCha20 subkey of `key` and
+    /// `personalization`, as by [`derive_subkey`], so each personalization gives an independent
+    /// keystream. Another implementation only reproduces it by deriving the same subkey.
+    pub fn new_personalized(
+        key: impl Into<Key>,
+        nonce: impl Into<Nonce>,
+        personalization: [u8; 16],
+        seek: u64,
+    ) -> Self {
+        let subkey = derive_subkey(key.into().0, personalization);
+        ChaCha20::new(subkey, nonce, seek)
+    }
+
+    const fn from_stream(stream: KeyStream) -> Self {
+        ChaCha20 {
+            start_inner: stream.inner,
+            start_seek: stream.seek,
+            stream,
+            #[cfg(feature = "debug_guards")]
+            produced: None,
+        }
+    }
+
+    /// Pack the key, nonce and position as `key || nonce || block counter (le) || offset`.
+    ///
+    /// The block counter alone cannot say how far into a block the cipher is, so the offset takes
+    /// a 49th byte and the full position round-trips through [`ChaCha20::from_bytes`]. The top bit
+    /// of that byte is set for [`ByteOrder::Big`].
+    pub fn to_bytes(&self) -> [u8; 49] {
+        let mut bytes = [0u8; 49];
+        bytes[..32].copy_from_slice(&self.stream.key);
+        bytes[32..44].copy_from_slice(&self.stream.nonce);
+        bytes[44..48].copy_from_slice(&self.stream.inner.to_le_bytes());
+        bytes[48] = self.stream.seek as u8;
+        if self.stream.order == ByteOrder::Big {
+            bytes[48] |= BIG_ENDIAN_FLAG;
+        }
+        bytes
+    }
+
+    /// Make a new instance of ChaCha20 from the layout written by [`ChaCha20::to_bytes`].
+    ///
+    /// Returns [`ChaChaError::InvalidPosition`] if the offset is not less than 64, other than the
+    /// offset of 64 into block `u32::MAX` written for an exhausted cipher.
+    pub fn from_bytes(bytes: &[u8; 49]) -> Result<Self, ChaChaError> {
+        let key = bytes[..32]
+            .try_into()
+            .expect("Valid slice of 49 byte array.");
+        let nonce = bytes[32..44]
+            .try_into()
+            .expect("Valid slice of 49 byte array.");
+        let block = u32::from_le_bytes(
+            bytes[44..48]
+                .try_into()
+                .expect("Valid slice of 49 byte array."),
+        );
+        let offset = (bytes[48] & !BIG_ENDIAN_FLAG) as usize;
+        if !valid_offset(block, offset) {
+            return Err(ChaChaError::InvalidPosition);
+        }
+        let mut stream = KeyStream::at(key, nonce, block, offset);
+        if bytes[48] & BIG_ENDIAN_FLAG != 0 {
+            stream.order = ByteOrder::Big;
+        }
+        Ok(ChaCha20::from_stream(stream))
+    }
+
+    /// Make a new cipher with the same key, nonce and byte order, starting at `block`.
+    ///
+    /// The original cipher is left untouched, so several forks can work on different parts of one keystream.
+    pub fn clone_at(&self, block: u32) -> ChaCha20 {
+        let mut stream = KeyStream::at(self.stream.key, self.stream.nonce, block, 0);
+        stream.order = self.stream.order;
+        ChaCha20::from_stream(stream)
+    }
+
+    /// Make a new cipher with the same key and byte order and a fresh `nonce`, starting at block 0.
+    ///
+    /// The key is copied internally and never returned, so a sibling cipher can be made without
+    /// handling the raw key. Each nonce must only ever be used once per key.
+    ///
+    /// ```rust
+    /// use chacha20::{nonce_from_counter, ChaCha20};
+    /// let session = ChaCha20::new([0x42; 32], nonce_from_counter([0; 4], 0), 0);
+    /// for counter in 1..4 {
+    ///     let mut message = *b"hello";
+    ///     session.with_nonce(nonce_from_counter([0; 4], counter)).apply_keystream(&mut message);
+    /// }
+    /// ```
+    pub fn with_nonce(&self, nonce: [u8; 12]) -> ChaCha20 {
+        let mut stream = KeyStream::at(self.stream.key, nonce, 0, 0);
+        stream.order = self.stream.order;
+        ChaCha20::from_stream(stream)
+    }
+
+    /// Make a new instance of ChaCha20 for one chunk of a file, starting at block 0.
+    ///
+    /// The nonce is `file_id || chunk_offset` with the offset in little-endian, so the same
+    /// `(file_id, chunk_offset)` always produces the same keystream and distinct pairs never share one.
+    /// Each `(file_id, chunk_offset)` pair must only ever be used once per key.
+    pub fn for_chunk(key: [u8; 32], file_id: [u8; 8], chunk_offset: u32) -> Self {
+        let mut nonce = [0u8; 12];
+        nonce[..8].copy_from_slice(&file_id);
+        nonce[8..].copy_from_slice(&chunk_offset.to_le_bytes());
+        ChaCha20::new_from_block(key, nonce, 0)
+    }
+
+    /// Apply the keystream to a message.
+    ///
+    /// An empty message leaves the position unchanged.
+    pub fn apply_keystream<'a>(&'a mut self, to: &'a mut [u8]) -> &'a [u8] {
+        if to.is_empty() {
+            return to;
+        }
+        self.stream.assert_remaining(to.len());
+        #[cfg(feature = "debug_guards")]
+        self.guard_keystream_reuse(to.len());
+        self.xor_keystream(to);
+        to
+    }
+
+    /// Apply one byte of keystream to `byte` and return it, for byte-at-a-time pipelines such as
+    /// a UART interrupt handler.
+    ///
+    /// Unless the `constant-path` feature is on, the current block is cached, so only every 64th
+    /// call runs the block function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keystream is exhausted.
+    pub fn feed(&mut self, byte: u8) -> u8 {
+        let mut buf = [byte];
+        self.apply_keystream(&mut buf);
+        buf[0]
+    }
+
+    /// Apply the keystream to a message, returning the number of keystream bytes consumed.
+    ///
+    /// This is always `to.len()`, as the position advances by exactly the bytes applied.
+    pub fn apply_keystream_counted(&mut self, to: &mut [u8]) -> usize {
+        let start = self.position();
+        self.apply_keystream(to);
+        (self.position() - start) as usize
+    }
+
+    /// Apply the keystream to `to`, continuing from the current position within a block.
+    fn xor_keystream(&mut self, to: &mut [u8]) {
+        for chunk in to.chunks_mut(CHACHA_BLOCKSIZE) {
+            let kstream = self.stream.window(chunk.len());
+            self.stream.advance(chunk.len());
+            for (c, k) in chunk.iter_mut().zip(kstream.iter()) {
+                *c ^= *k
+            }
+        }
+    }
+
+    /// Apply the keystream of both this cipher and `other` to `to` in one pass.
+    ///
+    /// Both positions advance by `to.len()`, so the result matches applying each keystream in turn.
+    pub fn apply_keystream_xor(&mut self, other: &mut ChaCha20, to: &mut [u8]) {
+        self.stream.assert_remaining(to.len());
+        other.stream.assert_remaining(to.len());
+        #[cfg(feature = "debug_guards")]
+        {
+            self.guard_keystream_reuse(to.len());
+            other.guard_keystream_reuse(to.len());
+        }
+        for chunk in to.chunks_mut(CHACHA_BLOCKSIZE) {
+            let ours = self.stream.current();
+            let theirs = other.stream.current();
+            self.stream.advance(chunk.len());
+            other.stream.advance(chunk.len());
+            for ((c, a), b) in chunk.iter_mut().zip(ours.iter()).zip(theirs.iter()) {
+                *c ^= *a ^ *b
+            }
+        }
+    }
+
+    /// Apply the keystream to `to` only if `apply` is true, without branching on `apply`.
+    ///
+    /// The keystream is always computed and the position always advances by `to.len()`; `apply`
+    /// only selects, through a `0x00` or `0xFF` mask, whether it is XORed into `to`.
+    pub fn apply_keystream_conditional(&mut self, to: &mut [u8], apply: bool) {
+        self.stream.assert_remaining(to.len());
+        #[cfg(feature = "debug_guards")]
+        self.guard_keystream_reuse(to.len());
+        let mask = (apply as u8).wrapping_neg();
+        for chunk in to.chunks_mut(CHACHA_BLOCKSIZE) {
+            let kstream = self.stream.current();
+            self.stream.advance(chunk.len());
+            for (c, k) in chunk.iter_mut().zip(kstream.iter()) {
+                *c ^= *k & mask
+            }
+        }
+    }
+
+    /// Apply the keystream to `len` bytes of a ring buffer starting at `start`, wrapping around the end of `buf`.
+    ///
+    /// `buf[start..]` and the wrapped-around front of `buf` are treated as one contiguous message, so
+    /// the result and the final position match [`ChaCha20::apply_keystream`] on the logically contiguous data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start` or `len` exceed the length of `buf`.
+    pub fn apply_keystream_wrapping(&mut self, buf: &mut [u8], start: usize, len: usize) {
+        assert!(
+            start <= buf.len() && len <= buf.len(),
+            "Region within the ring buffer."
+        );
+        self.stream.assert_remaining(len);
+        #[cfg(feature = "debug_guards")]
+        self.guard_keystream_reuse(len);
+        let head_len = len.min(buf.len() - start);
+        let (front, back) = buf.split_at_mut(start);
+        self.xor_keystream(&mut back[..head_len]);
+        self.xor_keystream(&mut front[..len - head_len]);
+    }
+
+    /// Apply the keystream to several buffers as if they were one contiguous message.
+    ///
+    /// The keystream continues from one buffer into the next, so the result matches
+    /// [`ChaCha20::apply_keystream`] over the buffers joined together.
+    pub fn apply_keystream_vectored(&mut self, bufs: &mut [&mut [u8]]) {
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        self.stream.assert_remaining(len);
+        #[cfg(feature = "debug_guards")]
+        self.guard_keystream_reuse(len);
+        for buf in bufs.iter_mut() {
+            self.xor_keystream(buf);
+        }
+    }
+
+    /// Apply the keystream to each chunk an iterator produces, continuing the keystream across chunks.
+    ///
+    /// Chunks may be any size, so the result matches [`ChaCha20::apply_keystream`] over the chunks
+    /// joined together.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a chunk would run past the end of the keystream. The remaining keystream is
+    /// checked one chunk at a time, so earlier chunks are left encrypted and the position past them.
+    pub fn apply_keystream_chunks<'b>(&mut self, chunks: impl Iterator<Item = &'b mut [u8]>) {
+        for chunk in chunks {
+            self.apply_keystream(chunk);
+        }
+    }
+
+    /// Apply the keystream to `to` in steps of `step` bytes, one step per call to [`Iterator::next`].
+    ///
+    /// Each step continues the keystream exactly where the previous one stopped, so running every
+    /// step matches a single [`ChaCha20::apply_keystream`] over `to`. Callers driving their own
+    /// executor can yield between steps.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `step` is zero.
+    pub fn apply_keystream_steps<'a>(&'a mut self, to: &'a mut [u8], step: usize) -> StepIter<'a> {
+        assert!(step > 0, "Step of at least one byte.");
+        StepIter {
+            cipher: self,
+            remaining: to,
+            step,
+        }
+    }
+
+    /// Apply the keystream to `input`, writing the result to `output` and leaving `input` untouched.
+    ///
+    /// Returns [`ChaChaError::LengthMismatch`] without consuming any keystream if the buffers differ in length.
+    pub fn encrypt(&mut self, input: &[u8], output: &mut [u8]) -> Result<(), ChaChaError> {
+        if input.len() != output.len() {
+            return Err(ChaChaError::LengthMismatch);
+        }
+        output.copy_from_slice(input);
+        self.apply_keystream(output);
+        Ok(())
+    }
+
+    /// Apply the keystream to a copy of `input`, returning the result in a freshly allocated vector.
+    #[cfg(feature = "alloc")]
+    pub fn encrypt_to_vec(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut output = input.to_vec();
+        self.apply_keystream(&mut output);
+        output
+    }
+
+    /// Decrypt a message in place, which is the same operation as [`ChaCha20::apply_keystream`].
+    pub fn decrypt(&mut self, to: &mut [u8]) {
+        self.apply_keystream(to);
+    }
+
+    /// Write keystream to `out`, matching [`ChaCha20::apply_keystream`] over a zeroed buffer.
+    pub fn fill_keystream(&mut self, out: &mut [u8]) {
+        self.stream.assert_remaining(out.len());
+        #[cfg(feature = "debug_guards")]
+        self.guard_keystream_reuse(out.len());
+        self.stream.fill(out);
+    }
+
+    /// Write keystream to `out` as words, advancing the position by `out.len() * 4` bytes.
+    ///
+    /// Each word is read from four bytes of keystream in the cipher's [`ByteOrder`], so from a
+    /// position that is a multiple of four the words are the state words after the block function.
+    pub fn fill_keystream_u32(&mut self, out: &mut [u32]) {
+        self.stream.assert_remaining(out.len() * 4);
+        #[cfg(feature = "debug_guards")]
+        self.guard_keystream_reuse(out.len() * 4);
+        let mut bytes = [0u8; CHACHA_BLOCKSIZE];
+        for words in out.chunks_mut(CHACHA_BLOCKSIZE / 4) {
+            let bytes = &mut bytes[..words.len() * 4];
+            self.stream.fill(bytes);
+            for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+                let chunk = chunk.try_into().expect("Chunk of four bytes.");
+                *word = match self.stream.order {
+                    ByteOrder::Little => u32::from_le_bytes(chunk),
+                    ByteOrder::Big => u32::from_be_bytes(chunk),
+                };
+            }
+        }
+    }
+
+    /// Deterministically assign `key_bytes` to one of `num_partitions` partitions.
+    ///
+    /// Consumes one block of keystream, which is mixed with `key_bytes` by the ChaCha block function.
+    /// The same key, nonce and position always give the same partition for the same `key_bytes`, so
+    /// every caller must use the cipher at the same position to agree. The partition is chosen by
+    /// rejection sampling and has no modulo bias.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_partitions` is zero.
+    pub fn partition(&mut self, key_bytes: &[u8], num_partitions: u32) -> u32 {
+        assert!(num_partitions > 0, "At least one partition.");
+        let mut block = [0u8; CHACHA_BLOCKSIZE];
+        self.apply_keystream(&mut block);
+        let mut state: [u32; 16] = [0; 16];
+        for (word, bytes) in state.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_le_bytes(bytes.try_into().expect("Valid slice of 4 bytes."));
+        }
+        absorb(&mut state, key_bytes);
+        // Values below 2^32 mod num_partitions would make the low partitions more likely.
+        let threshold = num_partitions.wrapping_neg() % num_partitions;
+        loop {
+            for &word in state.iter() {
+                if word >= threshold {
+                    return word % num_partitions;
+                }
+            }
+            chacha_block(&mut state);
+        }
+    }
+
+    /// Get the keystream block at a specified block.
+    ///
+    /// This also moves the cipher to the start of `block`, as [`ChaCha20::block`] does.
+    /// Use [`ChaCha20::peek_block`] to read a block without moving the cipher.
+    ///
+    /// Every `block` is valid, including `u32::MAX`, the last block before the counter runs out,
+    /// so this never panics and has no fallible variant.
+    #[must_use = "this moves the cipher to `block`; use `ChaCha20::block` to only move it"]
+    pub fn get_keystream(&mut self, block: u32) -> [u8; 64] {
+        self.block(block);
+        self.stream.current()
+    }
+
+    /// Iterate over successive 64-byte blocks of keystream, advancing the block counter by one per item.
+    ///
+    /// Each block starts at the current offset within its block, as [`KeyStream::next_block`] does.
+    /// The iterator ends once fewer than 64 bytes of keystream remain rather than wrapping, and
+    /// the last full block leaves the cipher exhausted.
+    pub fn keystream_block_iter(&mut self) -> BlockIter<'_> {
+        BlockIter { cipher: self }
+    }
+
+    /// Get the keystream block at a specified block, leaving the position of the cipher unchanged.
+    pub fn peek_block(&self, block: u32) -> [u8; 64] {
+        keystream_at_slice(
+            self.stream.key,
+            self.stream.nonce,
+            block,
+            0,
+            self.stream.order,
+        )
+    }
+
+    /// The number of times this cipher has run the ChaCha block function.
+    ///
+    /// Blocks computed by [`ChaCha20::peek_block`] are not counted, as it does not modify the cipher.
+    #[cfg(feature = "profiling")]
+    pub fn block_computations(&self) -> u64 {
+        self.stream.block_computations
+    }
+
+    /// The current index in the keystream.
+    pub fn position(&self) -> u64 {
+        self.stream.position()
+    }
+
+    /// The block counter placed in `state[12]` for the block at the current position.
+    ///
+    /// Unlike [`ChaCha20::position`], this ignores the offset within the block.
+    pub fn block_counter(&self) -> u32 {
+        self.stream.inner
+    }
+
+    /// Update the index of the keystream to an index in the keystream.
+    ///
+    /// Seeking to exactly the end of the keystream leaves the cipher exhausted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `seek` is past the end of the keystream.
+    pub fn seek(&mut self, seek: u64) {
+        self.stream.set_position(checked_seek(seek));
+    }
+
+    /// Skip `bytes` bytes of keystream without producing them.
+    ///
+    /// Advancing exactly to the end of the keystream leaves the cipher exhausted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the new position would pass the end of the keystream.
+    pub fn advance(&mut self, bytes: u64) {
+        let position = self
+            .position()
+            .checked_add(bytes)
+            .filter(|&position| position <= KEYSTREAM_LEN)
+            .expect("Seek within the 32-bit block counter.");
+        self.stream.set_position(position);
+    }
+
+    /// Update the index of the keystream as [`ChaCha20::seek`] does, returning the position it replaced.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `seek` is past the end of the keystream.
+    pub fn seek_returning(&mut self, seek: u64) -> CipherPosition {
+        let previous = self.save_position();
+        self.seek(seek);
+        previous
+    }
+
+    /// Update the index of the keystream to a block.
+    pub fn block(&mut self, block: u32) {
+        self.stream.inner = block;
+        self.stream.seek = 0;
+    }
+
+    /// Update the index of the keystream to a byte `offset` within a block.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is not less than 64, other than an offset of 64 into block `u32::MAX`,
+    /// which is the end of the keystream.
+    pub fn seek_block_and_offset(&mut self, block: u32, offset: usize) {
+        assert!(valid_offset(block, offset), "Offset within a block.");
+        self.stream.inner = block;
+        self.stream.seek = offset;
+    }
+
+    /// Save the current position, without the key or nonce.
+    pub fn save_position(&self) -> CipherPosition {
+        CipherPosition {
+            block: self.stream.inner,
+            offset: self.stream.seek as u8,
+        }
+    }
+
+    /// Return to a position saved by [`ChaCha20::save_position`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pos` is not a position [`ChaCha20::save_position`] can return.
+    pub fn restore_position(&mut self, pos: CipherPosition) {
+        self.seek_block_and_offset(pos.block, pos.offset as usize);
+    }
+
+    /// Move the cipher to an index in the keystream, consuming and returning it.
+    ///
+    /// The new position is also the one [`ChaCha20::reset`] returns to.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `seek` is past the end of the keystream.
+    pub fn with_seek(mut self, seek: u64) -> Self {
+        self.seek(seek);
+        self.start_inner = self.stream.inner;
+        self.start_seek = self.stream.seek;
+        self
+    }
+
+    /// Move the cipher to a block, consuming and returning it.
+    ///
+    /// The new position is also the one [`ChaCha20::reset`] returns to.
+    pub fn with_block(mut self, block: u32) -> Self {
+        self.block(block);
+        self.start_inner = self.stream.inner;
+        self.start_seek = self.stream.seek;
+        self
+    }
+
+    /// Return the cipher to the position it was constructed at.
+    ///
+    /// Under `debug_guards` the keystream applied so far is forgotten, so decrypting after a reset
+    /// is not reported as reuse.
+    pub fn reset(&mut self) {
+        self.stream.inner = self.start_inner;
+        self.stream.seek = self.start_seek;
+        #[cfg(feature = "profiling")]
+        {
+            self.stream.block_computations = 0;
+        }
+        #[cfg(feature = "debug_guards")]
+        {
+            self.produced = None;
+        }
+    }
+
+    /// Replace the key with the next 32 bytes of keystream and start again from block 0.
+    ///
+    /// The cipher no longer holds the old key or its cached block, so a later leak of the cipher's
+    /// state does not expose keystream produced before the rekey. Copies the compiler made
+    /// elsewhere are not wiped. Both sides of a session must rekey at the same position.
+    ///
+    /// # Panics
+    ///
+    /// Panics if fewer than 32 bytes of keystream remain.
+    pub fn rekey(&mut self) {
+        let mut next = [0u8; 32];
+        self.fill_keystream(&mut next);
+        self.stream.key = next;
+        if let Some((_, kstream)) = self.stream.cache.as_mut() {
+            *kstream = [0; 64];
+        }
+        self.stream.cache = None;
+        self.stream.inner = 0;
+        self.stream.seek = 0;
+        self.start_inner = 0;
+        self.start_seek = 0;
+        #[cfg(feature = "debug_guards")]
+        {
+            self.produced = None;
+        }
+    }
+
+    /// Whether the next `len` bytes of keystream overlap keystream this instance already applied.
+    ///
+    /// Only the span between the lowest and highest positions applied is tracked, so seeking
+    /// back into a gap that was skipped over is also reported.
+    #[cfg(feature = "debug_guards")]
+    pub(crate) fn would_reuse_keystream(&self, len: usize) -> bool {
+        let start = self.position();
+        let end = start + len as u64;
+        match self.produced {
+            Some((low, high)) => len != 0 && end > low && start < high,
+            None => false,
+        }
+    }
+
+    /// Panic if the next `len` bytes of keystream overlap keystream this instance already applied.
+    #[cfg(feature = "debug_guards")]
+    fn guard_keystream_reuse(&mut self, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let start = self.position();
+        let end = start + len as u64;
+        self.produced = match self.produced {
+            Some((low, high)) => {
+                assert!(
+                    !self.would_reuse_keystream(len),
+                    "Keystream reused: bytes {}..{} overlap already applied bytes {}..{}.",
+                    start,
+                    end,
+                    low,
+                    high
+                );
+                Some((low.min(start), high.max(end)))
+            }
+            None => Some((start, end)),
+        };
+    }
+
+    /// The number of 64-byte blocks that can still be produced before the 32-bit block counter overflows.
+    pub fn remaining_blocks(&self) -> u64 {
+        if self.is_exhausted() {
+            return 0;
+        }
+        (u32::MAX as u64) - self.stream.inner as u64 + 1
+    }
+
+    /// Whether every byte of keystream up to the end of block `u32::MAX` has been used.
+    ///
+    /// Once exhausted, applying the keystream to a non-empty message panics, so callers can rekey before.
+    pub fn is_exhausted(&self) -> bool {
+        self.position() == KEYSTREAM_LEN
+    }
+}
+
+/// A position in the keystream, saved by [`ChaCha20::save_position`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CipherPosition {
+    /// The block counter.
+    pub block: u32,
+    /// The offset into the block, less than 64 except for the offset of 64 into block `u32::MAX`
+    /// that marks an exhausted cipher.
+    pub offset: u8,
+}
+
+/// The bit of the offset byte written by [`ChaCha20::to_bytes`] that marks [`ByteOrder::Big`].
+const BIG_ENDIAN_FLAG: u8 = 0x80;
+
+/// Check that `seek` does not pass the end of the keystream, returning it.
+fn checked_seek(seek: u64) -> u64 {
+    assert!(
+        seek <= KEYSTREAM_LEN,
+        "Seek within the 32-bit block counter."
+    );
+    seek
+}
+
+/// Whether `offset` into `block` is a position the cipher can hold.
+fn valid_offset(block: u32, offset: usize) -> bool {
+    offset < CHACHA_BLOCKSIZE || (block == u32::MAX && offset == CHACHA_BLOCKSIZE)
+}
+
+/// Applies the keystream to a message one step at a time, created by [`ChaCha20::apply_keystream_steps`].
+///
+/// Each item is the number of bytes the step encrypted.
+#[derive(Debug)]
+pub struct StepIter<'a> {
+    cipher: &'a mut ChaCha20,
+    remaining: &'a mut [u8],
+    step: usize,
+}
+
+impl<'a> Iterator for StepIter<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.remaining.is_empty() {
+            return None;
+        }
+        let len = self.step.min(self.remaining.len());
+        self.cipher.stream.assert_remaining(len);
+        #[cfg(feature = "debug_guards")]
+        self.cipher.guard_keystream_reuse(len);
+        let (current, rest) = ::core::mem::take(&mut self.remaining).split_at_mut(len);
+        self.cipher.xor_keystream(current);
+        self.remaining = rest;
+        Some(len)
+    }
+}
+
+/// Yields successive blocks of keystream, created by [`ChaCha20::keystream_block_iter`].
+#[derive(Debug)]
+pub struct BlockIter<'a> {
+    cipher: &'a mut ChaCha20,
+}
+
+impl<'a> Iterator for BlockIter<'a> {
+    type Item = [u8; 64];
+
+    fn next(&mut self) -> Option<[u8; 64]> {
+        if self.cipher.position() + CHACHA_BLOCKSIZE as u64 > KEYSTREAM_LEN {
+            return None;
+        }
+        #[cfg(feature = "debug_guards")]
+        self.cipher.guard_keystream_reuse(CHACHA_BLOCKSIZE);
+        let kstream = self.cipher.stream.current();
+        self.cipher.stream.advance(CHACHA_BLOCKSIZE);
+        Some(kstream)
+    }
+}
+
+impl PartialEq for ChaCha20 {
+    /// Compares the key in constant time, the nonce and position normally.
+    fn eq(&self, other: &Self) -> bool {
+        self.stream == other.stream
+            && self.start_inner == other.start_inner
+            && self.start_seek == other.start_seek
+    }
+}
+
+impl Eq for ChaCha20 {}
+
+impl TryFrom<&[u8]> for ChaCha20 {
+    type Error = ChaChaError;
+
+    /// Make a new instance of ChaCha20 at the start of the keystream from a 32-byte key followed
+    /// by a 12-byte nonce.
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len() != 44 {
+            return Err(ChaChaError::InvalidKeyNonceLength);
+        }
+        let key: [u8; 32] = bytes[..32]
+            .try_into()
+            .expect("Valid slice of 44 byte slice.");
+        let nonce: [u8; 12] = bytes[32..]
+            .try_into()
+            .expect("Valid slice of 44 byte slice.");
+        Ok(ChaCha20::new(key, nonce, 0))
+    }
+}
+
+/// A seekable stream cipher, for code generic over cipher variants.
+pub trait StreamCipher {
+    /// The length of a key in bytes.
+    const KEY_SIZE: usize;
+    /// The length of a nonce in bytes.
+    const NONCE_SIZE: usize;
+    /// The length of a block of keystream in bytes.
+    const BLOCK_SIZE: usize;
+    /// The number of rounds of the block function.
+    const ROUNDS: usize;
+
+    /// XOR the keystream at the current position into `to`, advancing past it.
+    fn apply_keystream(&mut self, to: &mut [u8]);
+
+    /// Move the keystream to byte index `seek`.
+    fn seek(&mut self, seek: u64);
+
+    /// The byte index of the next keystream byte.
+    fn position(&self) -> u64;
+}
+
+impl StreamCipher for ChaCha20 {
+    const KEY_SIZE: usize = ChaCha20::KEY_SIZE;
+    const NONCE_SIZE: usize = ChaCha20::NONCE_SIZE;
+    const BLOCK_SIZE: usize = ChaCha20::BLOCK_SIZE;
+    const ROUNDS: usize = ChaCha20::ROUNDS;
+
+    fn apply_keystream(&mut self, to: &mut [u8]) {
+        ChaCha20::apply_keystream(self, to);
+    }
+
+    fn seek(&mut self, seek: u64) {
+        ChaCha20::seek(self, seek);
+    }
+
+    fn position(&self) -> u64 {
+        ChaCha20::position(self)
+    }
+}
+
+/// Compare two equal-length byte arrays without branching on their contents.
+pub(crate) fn constant_time_eq<const N: usize>(a: &[u8; N], b: &[u8; N]) -> bool {
+    let mut acc = 0u8;
+    for (x, y) in a.iter().zip(b.iter()) {
+        acc |= x ^ y;
+    }
+    acc == 0
+}
+
+fn keystream_from_state(state: &mut [u32; 16], order: ByteOrder) -> [u8; 64] {
+    let mut keystream: [u8; 64] = [0; 64];
+    let mut index = 0;
+    for &element in state.iter() {
+        let bytes = match order {
+            ByteOrder::Little => element.to_le_bytes(),
+            ByteOrder::Big => element.to_be_bytes(),
+        };
+        keystream[index..index + 4].copy_from_slice(&bytes);
+        index += 4;
+    }
+    keystream
+}
+
+/// The number of ChaCha blocks [`keystream_at_slice`] computes for a given `seek`.
+#[cfg(feature = "profiling")]
+const fn keystream_block_computations(seek: usize) -> u64 {
+    if seek == 0 && !cfg!(feature = "constant-path") {
+        1
+    } else {
+        2
+    }
+}
+
+/// The 64 bytes of keystream starting `seek` bytes into block `inner`.
+///
+/// Block `inner + 1` is only computed when `seek` is non-zero and the window spans it, and the
+/// same state is reused for both blocks. The `constant-path` feature computes both blocks for
+/// every call instead, so control flow never depends on the position in the keystream, at the
+/// cost of a wasted block on aligned reads.
+///
+/// There is no block after `u32::MAX`, so the bytes of the window past the end of the keystream are
+/// zero rather than wrapping around to block 0.
+pub(crate) fn keystream_at_slice(
+    key: [u8; 32],
+    nonce: [u8; 12],
+    inner: u32,
+    seek: usize,
+    order: ByteOrder,
+) -> [u8; 64] {
+    let mut state = prepare_state(key, nonce, inner);
+    chacha_block(&mut state);
+    let first_half = keystream_from_state(&mut state, order);
+    if seek == 0 && !cfg!(feature = "constant-path") {
+        return first_half;
+    }
+    state = prepare_state(key, nonce, inner.wrapping_add(1));
+    chacha_block(&mut state);
+    let mut second_half = keystream_from_state(&mut state, order);
+    // All ones unless `inner` is the last block, masked in so the end of the keystream does not branch.
+    let mask = 0u8.wrapping_sub((inner != u32::MAX) as u8);
+    for byte in second_half.iter_mut() {
+        *byte &= mask;
+    }
+    let mut kstream: [u8; 64] = [0; 64];
+    kstream[..CHACHA_BLOCKSIZE - seek].copy_from_slice(&first_half[seek..]);
+    kstream[CHACHA_BLOCKSIZE - seek..].copy_from_slice(&second_half[..seek]);
+    kstream
+}
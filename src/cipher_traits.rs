@@ -0,0 +1,69 @@
+//! Implementations of the RustCrypto `cipher` traits, so [`ChaCha20`] can be used by code
+//! written against them.
+
+use crate::{ChaCha20, CHACHA_BLOCKSIZE, KEYSTREAM_LEN};
+use cipher::consts::{U12, U32};
+use cipher::inout::InOutBuf;
+use cipher::{
+    Iv, IvSizeUser, KeyIvInit, KeySizeUser, OverflowError, SeekNum, StreamCipher,
+    StreamCipherError, StreamCipherSeek,
+};
+
+impl KeySizeUser for ChaCha20 {
+    type KeySize = U32;
+}
+
+impl IvSizeUser for ChaCha20 {
+    type IvSize = U12;
+}
+
+impl KeyIvInit for ChaCha20 {
+    fn new(key: &cipher::Key<Self>, iv: &Iv<Self>) -> Self {
+        let key: [u8; 32] = (*key).into();
+        let nonce: [u8; 12] = (*iv).into();
+        ChaCha20::new(key, nonce, 0)
+    }
+}
+
+impl StreamCipher for ChaCha20 {
+    fn try_apply_keystream_inout(
+        &mut self,
+        mut buf: InOutBuf<'_, '_, u8>,
+    ) -> Result<(), StreamCipherError> {
+        if self.position() + buf.len() as u64 > KEYSTREAM_LEN {
+            return Err(StreamCipherError);
+        }
+        let mut keystream = [0u8; CHACHA_BLOCKSIZE];
+        while !buf.is_empty() {
+            let len = buf.len().min(CHACHA_BLOCKSIZE);
+            let (mut chunk, rest) = buf.split_at(len);
+            self.fill_keystream(&mut keystream[..len]);
+            chunk.xor_in2out(&keystream[..len]);
+            buf = rest;
+        }
+        Ok(())
+    }
+}
+
+impl StreamCipherSeek for ChaCha20 {
+    fn try_current_pos<T: SeekNum>(&self) -> Result<T, OverflowError> {
+        let position = self.position();
+        let byte = (position % CHACHA_BLOCKSIZE as u64) as u8;
+        // `from_block_byte` counts a partially used block as already consumed.
+        let block = position / CHACHA_BLOCKSIZE as u64 + u64::from(byte != 0);
+        T::from_block_byte(block, byte, CHACHA_BLOCKSIZE as u8)
+    }
+
+    fn try_seek<T: SeekNum>(&mut self, pos: T) -> Result<(), StreamCipherError> {
+        let (block, byte): (u64, u8) = pos
+            .into_block_byte(CHACHA_BLOCKSIZE as u8)
+            .map_err(|_| StreamCipherError)?;
+        let position = block
+            .checked_mul(CHACHA_BLOCKSIZE as u64)
+            .map(|start| start + byte as u64)
+            .filter(|&position| position <= KEYSTREAM_LEN)
+            .ok_or(StreamCipherError)?;
+        self.seek(position);
+        Ok(())
+    }
+}
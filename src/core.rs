@@ -0,0 +1,245 @@
+//! The ChaCha20 block function, for building constructions other than the stream cipher.
+
+use crate::CHACHA_BLOCKSIZE;
+
+const WORD_1: u32 = 0x61707865;
+const WORD_2: u32 = 0x3320646e;
+const WORD_3: u32 = 0x79622d32;
+const WORD_4: u32 = 0x6b206574;
+const CHACHA_ROUND_INDICIES: [(usize, usize, usize, usize); 8] = [
+    (0, 4, 8, 12),
+    (1, 5, 9, 13),
+    (2, 6, 10, 14),
+    (3, 7, 11, 15),
+    (0, 5, 10, 15),
+    (1, 6, 11, 12),
+    (2, 7, 8, 13),
+    (3, 4, 9, 14),
+];
+
+pub(crate) fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+fn double_round(state: &mut [u32; 16]) {
+    for (a, b, c, d) in CHACHA_ROUND_INDICIES {
+        quarter_round(state, a, b, c, d);
+    }
+}
+
+pub(crate) fn chacha_block(state: &mut [u32; 16]) {
+    let initial_state = *state;
+    for _ in 0..10 {
+        double_round(state)
+    }
+    for (modified, initial) in state.iter_mut().zip(initial_state.iter()) {
+        *modified = modified.wrapping_add(*initial)
+    }
+}
+
+/// XOR `bytes` into `state` a block at a time, running the block function after each block and
+/// once more after folding in the length, so inputs that differ only in trailing zeros differ.
+pub(crate) fn absorb(state: &mut [u32; 16], bytes: &[u8]) {
+    for chunk in bytes.chunks(CHACHA_BLOCKSIZE) {
+        for (i, &byte) in chunk.iter().enumerate() {
+            state[i / 4] ^= (byte as u32) << (8 * (i % 4));
+        }
+        chacha_block(state);
+    }
+    state[0] ^= bytes.len() as u32;
+    chacha_block(state);
+}
+
+/// The HChaCha20 function: the ChaCha rounds without the final addition, keeping the first and last rows.
+pub(crate) fn hchacha20(key: [u8; 32], nonce: [u8; 16]) -> [u8; 32] {
+    let mut words = [0u8; 12];
+    words.copy_from_slice(&nonce[4..]);
+    let mut state = prepare_state(key, words, 0);
+    state[12] = u32::from_le_bytes(
+        nonce[..4]
+            .try_into()
+            .expect("Valid slice of 16 byte array."),
+    );
+    for _ in 0..10 {
+        double_round(&mut state)
+    }
+    let mut out = [0u8; 32];
+    for (chunk, word) in out
+        .chunks_exact_mut(4)
+        .zip(state[..4].iter().chain(state[12..].iter()))
+    {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+pub(crate) fn prepare_state(key: [u8; 32], nonce: [u8; 12], count: u32) -> [u32; 16] {
+    let mut state: [u32; 16] = [0; 16];
+    state[0] = WORD_1;
+    state[1] = WORD_2;
+    state[2] = WORD_3;
+    state[3] = WORD_4;
+    state[4] = u32::from_le_bytes(key[0..4].try_into().expect("Valid slice of 32 byte array."));
+    state[5] = u32::from_le_bytes(key[4..8].try_into().expect("Valid slice of 32 byte array."));
+    state[6] = u32::from_le_bytes(
+        key[8..12]
+            .try_into()
+            .expect("Valid slice of 32 byte array."),
+    );
+    state[7] = u32::from_le_bytes(
+        key[12..16]
+            .try_into()
+            .expect("Valid slice of 32 byte array."),
+    );
+    state[8] = u32::from_le_bytes(
+        key[16..20]
+            .try_into()
+            .expect("Valid slice of 32 byte array."),
+    );
+    state[9] = u32::from_le_bytes(
+        key[20..24]
+            .try_into()
+            .expect("Valid slice of 32 byte array."),
+    );
+    state[10] = u32::from_le_bytes(
+        key[24..28]
+            .try_into()
+            .expect("Valid slice of 32 byte array."),
+    );
+    state[11] = u32::from_le_bytes(
+        key[28..32]
+            .try_into()
+            .expect("Valid slice of 32 byte array."),
+    );
+    state[12] = count;
+    state[13] = u32::from_le_bytes(
+        nonce[0..4]
+            .try_into()
+            .expect("Valid slice of 32 byte array."),
+    );
+    state[14] = u32::from_le_bytes(
+        nonce[4..8]
+            .try_into()
+            .expect("Valid slice of 32 byte array."),
+    );
+    state[15] = u32::from_le_bytes(
+        nonce[8..12]
+            .try_into()
+            .expect("Valid slice of 32 byte array."),
+    );
+    state
+}
+
+/// Run the 20-round ChaCha block function over `state`, adding the input state back in at the end.
+///
+/// ```rust
+/// use chacha20::core::block;
+/// let state: [u32; 16] = [
+///     0x61707865, 0x3320646e, 0x79622d32, 0x6b206574,
+///     0x03020100, 0x07060504, 0x0b0a0908, 0x0f0e0d0c,
+///     0x13121110, 0x17161514, 0x1b1a1918, 0x1f1e1d1c,
+///     0x00000001, 0x09000000, 0x4a000000, 0x00000000,
+/// ];
+/// let expected: [u32; 16] = [
+///     0xe4e7f110, 0x15593bd1, 0x1fdd0f50, 0xc47120a3,
+///     0xc7f4d1c7, 0x0368c033, 0x9aaa2204, 0x4e6cd4c3,
+///     0x466482d2, 0x09aa9f07, 0x05d7c214, 0xa2028bd9,
+///     0xd19c12b5, 0xb94e16de, 0xe883d0cb, 0x4e3c50a2,
+/// ];
+/// assert_eq!(block(&state), expected);
+/// ```
+pub fn block(state: &[u32; 16]) -> [u32; 16] {
+    let mut out = *state;
+    chacha_block(&mut out);
+    out
+}
+
+/// The state the block function starts from for `key`, `nonce` and block `counter`, before any rounds.
+///
+/// ```rust
+/// use chacha20::core::state_words;
+/// let mut key = [0u8; 32];
+/// for (i, byte) in key.iter_mut().enumerate() {
+///     *byte = i as u8;
+/// }
+/// let nonce = [0, 0, 0, 0x09, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+/// let expected: [u32; 16] = [
+///     0x61707865, 0x3320646e, 0x79622d32, 0x6b206574,
+///     0x03020100, 0x07060504, 0x0b0a0908, 0x0f0e0d0c,
+///     0x13121110, 0x17161514, 0x1b1a1918, 0x1f1e1d1c,
+///     0x00000001, 0x09000000, 0x4a000000, 0x00000000,
+/// ];
+/// assert_eq!(state_words(key, nonce, 1), expected);
+/// ```
+pub fn state_words(key: [u8; 32], nonce: [u8; 12], counter: u32) -> [u32; 16] {
+    prepare_state(key, nonce, counter)
+}
+
+/// How words 12 to 15 of the state are split between the block counter and the nonce.
+pub trait CounterWidth {
+    /// The block counter, filling word 12 and possibly word 13.
+    type Counter;
+    /// The nonce, filling the words the counter leaves.
+    type Nonce;
+
+    /// The state before any rounds, as [`state_words`] gives for the IETF layout.
+    fn state_words(key: [u8; 32], nonce: Self::Nonce, counter: Self::Counter) -> [u32; 16];
+}
+
+/// The RFC 7539 layout: a 32-bit counter in word 12 and a 96-bit nonce in words 13 to 15.
+///
+/// This is the layout [`crate::ChaCha20`] uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ietf;
+
+impl CounterWidth for Ietf {
+    type Counter = u32;
+    type Nonce = [u8; 12];
+
+    fn state_words(key: [u8; 32], nonce: [u8; 12], counter: u32) -> [u32; 16] {
+        state_words(key, nonce, counter)
+    }
+}
+
+/// The original layout by Bernstein: a 64-bit counter in words 12 and 13 and a 64-bit nonce in
+/// words 14 and 15.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Djb;
+
+impl CounterWidth for Djb {
+    type Counter = u64;
+    type Nonce = [u8; 8];
+
+    fn state_words(key: [u8; 32], nonce: [u8; 8], counter: u64) -> [u32; 16] {
+        // The high counter word takes the place of the first nonce word of the IETF layout.
+        let mut words = [0u8; 12];
+        words[..4].copy_from_slice(&((counter >> 32) as u32).to_le_bytes());
+        words[4..].copy_from_slice(&nonce);
+        state_words(key, words, counter as u32)
+    }
+}
+
+/// The state before any rounds for the counter layout `W`.
+///
+/// ```rust
+/// use chacha20::core::{block, state_words_for, Djb, Ietf};
+/// // The all-zero key and nonce give the same first block in both layouts.
+/// let ietf = block(&state_words_for::<Ietf>([0; 32], [0; 12], 0));
+/// let djb = block(&state_words_for::<Djb>([0; 32], [0; 8], 0));
+/// assert_eq!(ietf, djb);
+/// assert_eq!(djb[0], 0xade0b876);
+/// ```
+pub fn state_words_for<W: CounterWidth>(
+    key: [u8; 32],
+    nonce: W::Nonce,
+    counter: W::Counter,
+) -> [u32; 16] {
+    W::state_words(key, nonce, counter)
+}
@@ -0,0 +1,61 @@
+//! Errors reported by the cipher.
+
+/// Errors returned by the fallible operations of this crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChaChaError {
+    /// The input and output buffers have different lengths.
+    LengthMismatch,
+    /// A key was not 32 bytes long.
+    InvalidKeyLength,
+    /// A nonce was not 12 bytes long.
+    InvalidNonceLength,
+    /// A key followed by a nonce was not 44 bytes long.
+    InvalidKeyNonceLength,
+    /// A message failed authentication.
+    AuthenticationFailed,
+    /// A serialized position was not one the cipher can hold.
+    InvalidPosition,
+}
+
+impl ::core::fmt::Display for ChaChaError {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        let message = match self {
+            ChaChaError::LengthMismatch => "input and output lengths differ",
+            ChaChaError::InvalidKeyLength => "key must be 32 bytes",
+            ChaChaError::InvalidNonceLength => "nonce must be 12 bytes",
+            ChaChaError::InvalidKeyNonceLength => "key and nonce must be 44 bytes",
+            ChaChaError::AuthenticationFailed => "authentication failed",
+            ChaChaError::InvalidPosition => "offset must be within a block",
+        };
+        f.write_str(message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ChaChaError {}
+
+/// A key or nonce rejected by [`ChaCha20::new_production`](crate::ChaCha20::new_production) or
+/// [`ChaCha20::new_checked`](crate::ChaCha20::new_checked) as likely placeholder material.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeakInput {
+    /// The key is the `00..1f` key used throughout the RFC 7539 test vectors.
+    TestVectorKey,
+    /// Every byte of the key is zero.
+    ZeroKey,
+    /// Every byte of the nonce is zero.
+    ZeroNonce,
+}
+
+impl ::core::fmt::Display for WeakInput {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+        let message = match self {
+            WeakInput::TestVectorKey => "key is the RFC 7539 test vector key",
+            WeakInput::ZeroKey => "key is all zeros",
+            WeakInput::ZeroNonce => "nonce is all zeros",
+        };
+        f.write_str(message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for WeakInput {}
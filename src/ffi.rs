@@ -0,0 +1,112 @@
+//! C bindings over a heap-allocated cipher.
+//!
+//! Every function checks its pointers, the block counter and, with `debug_guards`, keystream
+//! reuse before touching the cipher, and reports failure through its return value rather than
+//! panicking across the FFI boundary.
+
+use crate::{ChaCha20, KEYSTREAM_LEN};
+use alloc::boxed::Box;
+
+/// The call succeeded.
+pub const CHACHA20_OK: i32 = 0;
+/// A required pointer was null.
+pub const CHACHA20_ERR_NULL: i32 = -1;
+/// The call would run past the end of the 32-bit block counter.
+pub const CHACHA20_ERR_OVERFLOW: i32 = -2;
+/// The call would reuse keystream already applied. Only returned with the `debug_guards` feature.
+pub const CHACHA20_ERR_REUSE: i32 = -3;
+
+/// An opaque cipher handle, created by [`chacha20_new`] and released by [`chacha20_free`].
+///
+/// C only ever holds a pointer to it. Its layout is not part of the interface, and its fields
+/// must not be read or written from C.
+pub struct CipherState {
+    cipher: ChaCha20,
+}
+
+/// Make a new cipher from an index in the keystream, returning null if an argument is invalid.
+///
+/// # Safety
+///
+/// `key` must point to 32 readable bytes and `nonce` to 12 readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chacha20_new(
+    key: *const u8,
+    nonce: *const u8,
+    seek: u64,
+) -> *mut CipherState {
+    if key.is_null() || nonce.is_null() || !seek_in_range(seek) {
+        return core::ptr::null_mut();
+    }
+    let key: [u8; 32] = core::ptr::read(key.cast());
+    let nonce: [u8; 12] = core::ptr::read(nonce.cast());
+    let cipher = ChaCha20::new(key, nonce, seek);
+    Box::into_raw(Box::new(CipherState { cipher }))
+}
+
+/// Apply the keystream to `len` bytes at `buf`.
+///
+/// # Safety
+///
+/// `state` must come from [`chacha20_new`] and not yet be freed. `buf` must point to `len`
+/// writable bytes, and may only be null when `len` is zero.
+#[no_mangle]
+pub unsafe extern "C" fn chacha20_apply(state: *mut CipherState, buf: *mut u8, len: usize) -> i32 {
+    let state = match state.as_mut() {
+        Some(state) => state,
+        None => return CHACHA20_ERR_NULL,
+    };
+    if len == 0 {
+        return CHACHA20_OK;
+    }
+    if buf.is_null() {
+        return CHACHA20_ERR_NULL;
+    }
+    // Report running off the end of the keystream rather than panicking in apply_keystream.
+    let end = state.cipher.position() + len as u64;
+    if end > KEYSTREAM_LEN {
+        return CHACHA20_ERR_OVERFLOW;
+    }
+    // Likewise report what the debug guard would panic on.
+    #[cfg(feature = "debug_guards")]
+    if state.cipher.would_reuse_keystream(len) {
+        return CHACHA20_ERR_REUSE;
+    }
+    let to = core::slice::from_raw_parts_mut(buf, len);
+    state.cipher.apply_keystream(to);
+    CHACHA20_OK
+}
+
+/// Update the index of the keystream to an index in the keystream.
+///
+/// # Safety
+///
+/// `state` must come from [`chacha20_new`] and not yet be freed.
+#[no_mangle]
+pub unsafe extern "C" fn chacha20_seek(state: *mut CipherState, seek: u64) -> i32 {
+    let state = match state.as_mut() {
+        Some(state) => state,
+        None => return CHACHA20_ERR_NULL,
+    };
+    if !seek_in_range(seek) {
+        return CHACHA20_ERR_OVERFLOW;
+    }
+    state.cipher.seek(seek);
+    CHACHA20_OK
+}
+
+/// Release a cipher made by [`chacha20_new`]. Passing null does nothing.
+///
+/// # Safety
+///
+/// `state` must come from [`chacha20_new`] and not yet be freed. It must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn chacha20_free(state: *mut CipherState) {
+    if !state.is_null() {
+        drop(Box::from_raw(state));
+    }
+}
+
+fn seek_in_range(seek: u64) -> bool {
+    seek <= KEYSTREAM_LEN
+}
@@ -5,8 +5,8 @@
 //! ## Features
 //!
 //! - [x] Stack-allocated
-//! - [x] No unsafe code blocks
-//! - [x] Zero dependencies
+//! - [x] No unsafe code blocks outside the optional `ffi` bindings
+//! - [x] No required dependencies
 //! - [x] Seek an index in the keystream or a block in the keystream.
 //!
 //! ## Usage
@@ -25,216 +25,44 @@
 //! chacha.seek(seek); // move the keystream index back to 42
 //! ```
 #![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(feature = "ffi"), forbid(unsafe_code))]
+#![cfg_attr(feature = "ffi", deny(unsafe_code))]
 
-const WORD_1: u32 = 0x61707865;
-const WORD_2: u32 = 0x3320646e;
-const WORD_3: u32 = 0x79622d32;
-const WORD_4: u32 = 0x6b206574;
-const CHACHA_ROUND_INDICIES: [(usize, usize, usize, usize); 8] = [
-    (0, 4, 8, 12),
-    (1, 5, 9, 13),
-    (2, 6, 10, 14),
-    (3, 7, 11, 15),
-    (0, 5, 10, 15),
-    (1, 6, 11, 12),
-    (2, 7, 8, 13),
-    (3, 4, 9, 14),
-];
-const CHACHA_BLOCKSIZE: usize = 64;
-
-/// The ChaCha20 stream cipher.
-#[derive(Debug)]
-pub struct ChaCha20 {
-    key: [u8; 32],
-    nonce: [u8; 12],
-    inner: u32,
-    seek: usize,
-}
-
-impl ChaCha20 {
-    /// Make a new instance of ChaCha20 from an index in the keystream.
-    pub fn new(key: [u8; 32], nonce: [u8; 12], seek: u32) -> Self {
-        let inner = seek / 64;
-        let seek = (seek % 64) as usize;
-        ChaCha20 {
-            key,
-            nonce,
-            inner,
-            seek,
-        }
-    }
-
-    /// Make a new instance of ChaCha20 from a block in the keystream.
-    pub fn new_from_block(key: [u8; 32], nonce: [u8; 12], block: u32) -> Self {
-        let inner = block;
-        let seek = 0;
-        ChaCha20 {
-            key,
-            nonce,
-            inner,
-            seek,
-        }
-    }
-
-    /// Apply the keystream to a message.
-    pub fn apply_keystream<'a>(&'a mut self, to: &'a mut [u8]) -> &[u8] {
-        let num_full_blocks = to.len() / CHACHA_BLOCKSIZE;
-        let mut j = 0;
-        while j < num_full_blocks {
-            let kstream = keystream_at_slice(self.key, self.nonce, self.inner, self.seek);
-            for (c, k) in to[j * CHACHA_BLOCKSIZE..(j + 1) * CHACHA_BLOCKSIZE]
-                .iter_mut()
-                .zip(kstream.iter())
-            {
-                *c ^= *k
-            }
-            j += 1;
-            self.inner += 1;
-        }
-        if to.len() % 64 > 0 {
-            let kstream = keystream_at_slice(self.key, self.nonce, self.inner, self.seek);
-            for (c, k) in to[j * CHACHA_BLOCKSIZE..].iter_mut().zip(kstream.iter()) {
-                *c ^= *k
-            }
-            self.inner += 1;
-        }
-        to
-    }
-
-    /// Get the keystream block at a specified block.
-    pub fn get_keystream(&mut self, block: u32) -> [u8; 64] {
-        self.block(block);
-        keystream_at_slice(self.key, self.nonce, self.inner, self.seek)
-    }
-
-    /// Update the index of the keystream to an index in the keystream.
-    pub fn seek(&mut self, seek: u32) {
-        self.inner = seek / 64;
-        self.seek = (seek % 64) as usize;
-    }
-
-    /// Update the index of the keystream to a block.
-    pub fn block(&mut self, block: u32) {
-        self.inner = block;
-        self.seek = 0;
-    }
-}
-
-fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
-    state[a] = state[a].wrapping_add(state[b]);
-    state[d] = (state[d] ^ state[a]).rotate_left(16);
-    state[c] = state[c].wrapping_add(state[d]);
-    state[b] = (state[b] ^ state[c]).rotate_left(12);
-    state[a] = state[a].wrapping_add(state[b]);
-    state[d] = (state[d] ^ state[a]).rotate_left(8);
-    state[c] = state[c].wrapping_add(state[d]);
-    state[b] = (state[b] ^ state[c]).rotate_left(7);
-}
+#[cfg(feature = "alloc")]
+extern crate alloc;
 
-fn double_round(state: &mut [u32; 16]) {
-    for (a, b, c, d) in CHACHA_ROUND_INDICIES {
-        quarter_round(state, a, b, c, d);
-    }
-}
-
-fn chacha_block(state: &mut [u32; 16]) {
-    let initial_state = *state;
-    for _ in 0..10 {
-        double_round(state)
-    }
-    for (modified, initial) in state.iter_mut().zip(initial_state.iter()) {
-        *modified = modified.wrapping_add(*initial)
-    }
-}
+#[cfg(all(feature = "std", not(test)))]
+extern crate std;
 
-fn prepare_state(key: [u8; 32], nonce: [u8; 12], count: u32) -> [u32; 16] {
-    let mut state: [u32; 16] = [0; 16];
-    state[0] = WORD_1;
-    state[1] = WORD_2;
-    state[2] = WORD_3;
-    state[3] = WORD_4;
-    state[4] = u32::from_le_bytes(key[0..4].try_into().expect("Valid slice of 32 byte array."));
-    state[5] = u32::from_le_bytes(key[4..8].try_into().expect("Valid slice of 32 byte array."));
-    state[6] = u32::from_le_bytes(
-        key[8..12]
-            .try_into()
-            .expect("Valid slice of 32 byte array."),
-    );
-    state[7] = u32::from_le_bytes(
-        key[12..16]
-            .try_into()
-            .expect("Valid slice of 32 byte array."),
-    );
-    state[8] = u32::from_le_bytes(
-        key[16..20]
-            .try_into()
-            .expect("Valid slice of 32 byte array."),
-    );
-    state[9] = u32::from_le_bytes(
-        key[20..24]
-            .try_into()
-            .expect("Valid slice of 32 byte array."),
-    );
-    state[10] = u32::from_le_bytes(
-        key[24..28]
-            .try_into()
-            .expect("Valid slice of 32 byte array."),
-    );
-    state[11] = u32::from_le_bytes(
-        key[28..32]
-            .try_into()
-            .expect("Valid slice of 32 byte array."),
-    );
-    state[12] = count;
-    state[13] = u32::from_le_bytes(
-        nonce[0..4]
-            .try_into()
-            .expect("Valid slice of 32 byte array."),
-    );
-    state[14] = u32::from_le_bytes(
-        nonce[4..8]
-            .try_into()
-            .expect("Valid slice of 32 byte array."),
-    );
-    state[15] = u32::from_le_bytes(
-        nonce[8..12]
-            .try_into()
-            .expect("Valid slice of 32 byte array."),
-    );
-    state
-}
+mod cipher;
+#[cfg(feature = "cipher-traits")]
+mod cipher_traits;
+pub mod core;
+mod error;
+#[cfg(feature = "ffi")]
+#[allow(unsafe_code)]
+pub mod ffi;
 
-fn keystream_from_state(state: &mut [u32; 16]) -> [u8; 64] {
-    let mut keystream: [u8; 64] = [0; 64];
-    let mut index = 0;
-    for &element in state.iter() {
-        let bytes = element.to_le_bytes();
-        keystream[index..index + 4].copy_from_slice(&bytes);
-        index += 4;
-    }
-    keystream
-}
+#[cfg(feature = "getrandom")]
+pub use cipher::random_nonce;
+pub use cipher::{
+    apply_keystream_stack_usage, chacha20_xor, derive_subkey, keyed_digest, keystream_block,
+    keystream_range, nonce_from_counter, xor_blocks, BlockIter, ByteOrder, ChaCha20,
+    CipherPosition, Key, KeyStream, Nonce, StepIter, StreamCipher, STACK_USAGE_PER_BLOCK,
+};
+pub use error::{ChaChaError, WeakInput};
 
-fn keystream_at_slice(key: [u8; 32], nonce: [u8; 12], inner: u32, seek: usize) -> [u8; 64] {
-    let mut keystream: [u8; 128] = [0; 128];
-    let mut state = prepare_state(key, nonce, inner);
-    chacha_block(&mut state);
-    let first_half = keystream_from_state(&mut state);
-    let mut state = prepare_state(key, nonce, inner + 1);
-    chacha_block(&mut state);
-    let second_half = keystream_from_state(&mut state);
-    keystream[..64].copy_from_slice(&first_half);
-    keystream[64..].copy_from_slice(&second_half);
-    let kstream: [u8; 64] = keystream[seek..seek + 64].try_into().expect("msg");
-    kstream
-}
+const CHACHA_BLOCKSIZE: usize = 64;
+/// The bytes of keystream a 32-bit block counter can address.
+const KEYSTREAM_LEN: u64 = (u32::MAX as u64 + 1) * CHACHA_BLOCKSIZE as u64;
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
-    use hex;
-    use rand::Rng;
+    use crate::cipher::{constant_time_eq, RFC_TEST_KEY};
+    use crate::core::{chacha_block, prepare_state, quarter_round};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use rustcrypto_chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
 
     #[test]
     fn test_quater_round() {
@@ -403,6 +231,11 @@ mod tests {
         assert_eq!(binding, to);
     }
 
+    #[test]
+    fn test_self_test() {
+        assert!(ChaCha20::self_test());
+    }
+
     #[test]
     fn test_new_from_block() {
         let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
@@ -416,39 +249,1752 @@ mod tests {
         let to = binding.as_mut_slice();
         chacha.apply_keystream(to);
         assert_eq!(to, hex::decode("6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0bf91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d807ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab77937365af90bbf74a35be6b40b8eedf2785e42874d").unwrap());
-        chacha.block(block);
-        chacha.apply_keystream(to);
+        let mut decryptor = ChaCha20::new(key, nonce, 0);
+        decryptor.block(block);
+        decryptor.apply_keystream(to);
         let binding = *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
         assert_eq!(binding, to);
     }
 
-    fn gen_garbage(garbage_len: u32) -> Vec<u8> {
-        let mut rng = rand::thread_rng();
-        let buffer: Vec<u8> = (0..garbage_len).map(|_| rng.gen()).collect();
-        buffer
+    #[test]
+    fn test_encrypt_out_of_place() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let count = 64;
+        let plaintext = *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let mut chacha = ChaCha20::new(key, nonce, count);
+        let mut output = [0u8; 114];
+        chacha.encrypt(&plaintext, &mut output).unwrap();
+        let mut chacha = ChaCha20::new(key, nonce, count);
+        let mut binding = plaintext;
+        let to = binding.as_mut_slice();
+        chacha.apply_keystream(to);
+        assert_eq!(output, to);
+        let mut short = [0u8; 3];
+        assert_eq!(
+            chacha.encrypt(&plaintext, &mut short),
+            Err(ChaChaError::LengthMismatch)
+        );
     }
 
     #[test]
-    fn test_fuzz_other() {
-        for _ in 0..100 {
-            let garbage_key = gen_garbage(32);
-            let key = garbage_key.as_slice().try_into().unwrap();
-            let garbage_nonce = gen_garbage(12);
-            let nonce = garbage_nonce.as_slice().try_into().unwrap();
-            for i in 0..10 {
-                let count: u32 = i * 11;
-                let mut chacha = ChaCha20::new(key, nonce, count);
-                let message = gen_garbage(129);
-                let mut message2 = message.clone();
-                let msg = message2.as_mut_slice();
-                chacha.apply_keystream(msg);
-                let mut cipher = chacha20::ChaCha20::new_from_slices(&key, &nonce)
-                    .expect("Valid keys and nonce.");
-                let mut buffer = message;
-                cipher.seek(count);
-                cipher.apply_keystream(&mut buffer);
-                assert_eq!(buffer.as_slice(), msg);
+    fn test_seek_beyond_u32_bytes() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let seek: u64 = 5_000_000_000;
+        let mut chacha = ChaCha20::new(key, nonce, seek);
+        assert_eq!(chacha.stream.inner, 78_125_000);
+        assert_eq!(chacha.stream.seek, 0);
+        let mut from_seek = [0u8; 100];
+        chacha.apply_keystream(&mut from_seek);
+        let mut chacha = ChaCha20::new_from_block(key, nonce, 78_125_000);
+        let mut from_block = [0u8; 100];
+        chacha.apply_keystream(&mut from_block);
+        assert_eq!(from_seek, from_block);
+        chacha.seek(seek + 17);
+        assert_eq!(chacha.stream.inner, 78_125_000);
+        assert_eq!(chacha.stream.seek, 17);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_seek_past_block_counter() {
+        let key = [0u8; 32];
+        let nonce = [0u8; 12];
+        ChaCha20::new(key, nonce, (u32::MAX as u64 + 1) * 64 + 1);
+    }
+
+    #[test]
+    fn test_remaining_blocks() {
+        let key = [0u8; 32];
+        let nonce = [0u8; 12];
+        let mut chacha = ChaCha20::new(key, nonce, 0);
+        assert_eq!(chacha.remaining_blocks(), 1 << 32);
+        chacha.apply_keystream(&mut [0u8; 128]);
+        assert_eq!(chacha.remaining_blocks(), (1 << 32) - 2);
+        chacha.block(u32::MAX);
+        assert_eq!(chacha.remaining_blocks(), 1);
+    }
+
+    #[test]
+    fn test_apply_keystream_stack_usage() {
+        assert_eq!(apply_keystream_stack_usage(0), 0);
+        assert_eq!(apply_keystream_stack_usage(3), STACK_USAGE_PER_BLOCK);
+        assert_eq!(apply_keystream_stack_usage(1 << 20), STACK_USAGE_PER_BLOCK);
+    }
+
+    #[test]
+    fn test_new_const() {
+        const KEY: [u8; 32] = [
+            0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23,
+            24, 25, 26, 27, 28, 29, 30, 31,
+        ];
+        const NONCE: [u8; 12] = [0, 0, 0, 0, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+        const CIPHER: ChaCha20 = ChaCha20::new_const(KEY, NONCE, 64);
+        let mut chacha = CIPHER;
+        let mut binding = *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let to = binding.as_mut_slice();
+        chacha.apply_keystream(to);
+        assert_eq!(to, hex::decode("6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0bf91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d807ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab77937365af90bbf74a35be6b40b8eedf2785e42874d").unwrap());
+    }
+
+    #[test]
+    fn test_new_production() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        assert_eq!(
+            ChaCha20::new_production(key, nonce, 0).unwrap_err(),
+            WeakInput::TestVectorKey
+        );
+        let mut chacha = ChaCha20::new(key, nonce, 0);
+        chacha.apply_keystream(&mut [0u8; 3]);
+        assert_eq!(
+            ChaCha20::new_production([0u8; 32], nonce, 0).unwrap_err(),
+            WeakInput::ZeroKey
+        );
+        assert_eq!(
+            ChaCha20::new_production([7u8; 32], [0u8; 12], 0).unwrap_err(),
+            WeakInput::ZeroNonce
+        );
+        assert!(ChaCha20::new_production([7u8; 32], nonce, 0).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_encrypt_to_vec() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let plaintext = *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let mut chacha = ChaCha20::new(key, nonce, 64);
+        let ciphertext = chacha.encrypt_to_vec(&plaintext);
+        let mut chacha = ChaCha20::new(key, nonce, 64);
+        let mut binding = plaintext;
+        let to = binding.as_mut_slice();
+        chacha.apply_keystream(to);
+        assert_eq!(ciphertext, to);
+    }
+
+    #[test]
+    fn test_for_chunk() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let file_id = *b"file0001";
+        let chacha = ChaCha20::for_chunk(key, file_id, 7);
+        assert_eq!(chacha.stream.nonce, *b"file0001\x07\x00\x00\x00");
+        assert_eq!(chacha.stream.inner, 0);
+        let mut first = [0u8; 256];
+        ChaCha20::for_chunk(key, file_id, 0).apply_keystream(&mut first);
+        let mut second = [0u8; 256];
+        ChaCha20::for_chunk(key, file_id, 1).apply_keystream(&mut second);
+        for a in first.chunks(64) {
+            for b in second.chunks(64) {
+                assert_ne!(a, b);
+            }
+        }
+        let mut again = [0u8; 256];
+        ChaCha20::for_chunk(key, file_id, 0).apply_keystream(&mut again);
+        assert_eq!(first, again);
+    }
+
+    #[test]
+    fn test_partial_eq() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        assert_eq!(ChaCha20::new(key, nonce, 64), ChaCha20::new(key, nonce, 64));
+        assert_eq!(
+            ChaCha20::new(key, nonce, 64),
+            ChaCha20::new_from_block(key, nonce, 1)
+        );
+        let mut other_key = key;
+        other_key[31] ^= 1;
+        assert_ne!(
+            ChaCha20::new(key, nonce, 64),
+            ChaCha20::new(other_key, nonce, 64)
+        );
+        assert_ne!(ChaCha20::new(key, nonce, 64), ChaCha20::new(key, nonce, 65));
+        assert_ne!(
+            ChaCha20::new(key, nonce, 64),
+            ChaCha20::new(key, [0u8; 12], 64)
+        );
+        assert!(constant_time_eq(&[1, 2, 3], &[1, 2, 3]));
+        assert!(!constant_time_eq(&[1, 2, 3], &[1, 2, 4]));
+    }
+
+    #[test]
+    fn test_apply_keystream_wrapping() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let ring: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let (start, len) = (150, 120);
+        let mut contiguous: Vec<u8> = ring[start..]
+            .iter()
+            .chain(ring[..70].iter())
+            .copied()
+            .collect();
+        let mut linear = ChaCha20::new(key, nonce, 17);
+        linear.apply_keystream(&mut contiguous);
+        let mut wrapped = ring.clone();
+        let mut chacha = ChaCha20::new(key, nonce, 17);
+        chacha.apply_keystream_wrapping(&mut wrapped, start, len);
+        assert_eq!(wrapped[start..], contiguous[..50]);
+        assert_eq!(wrapped[..70], contiguous[50..]);
+        assert_eq!(wrapped[70..start], ring[70..start]);
+        assert_eq!(chacha, linear);
+        let mut chacha = ChaCha20::new(key, nonce, 17);
+        chacha.apply_keystream_wrapping(&mut wrapped, start, len);
+        assert_eq!(wrapped, ring);
+    }
+
+    #[test]
+    fn test_partition_distribution() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let mut counts = [0u32; 8];
+        for i in 0u32..8000 {
+            let mut chacha = ChaCha20::new(key, nonce, 0);
+            let partition = chacha.partition(&i.to_le_bytes(), 8);
+            counts[partition as usize] += 1;
+        }
+        for count in counts {
+            assert!(
+                (800..1200).contains(&count),
+                "Unbalanced partition: {}",
+                count
+            );
+        }
+    }
+
+    #[test]
+    fn test_partition_reproducible() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let item = b"user:1234";
+        let mut first = ChaCha20::new(key, nonce, 0);
+        let mut second = ChaCha20::new(key, nonce, 0);
+        let partition = first.partition(item, 1000);
+        assert!(partition < 1000);
+        assert_eq!(partition, second.partition(item, 1000));
+        assert_eq!(first, second);
+        assert_eq!(first.stream.inner, 1);
+        assert_eq!(ChaCha20::new(key, nonce, 0).partition(item, 1), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "debug_guards")]
+    #[should_panic(expected = "Keystream reused")]
+    fn test_debug_guard_rewind() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let mut chacha = ChaCha20::new(key, nonce, 64);
+        chacha.apply_keystream(&mut [0u8; 100]);
+        chacha.seek(128);
+        chacha.apply_keystream(&mut [0u8; 10]);
+    }
+
+    #[test]
+    #[cfg(feature = "debug_guards")]
+    fn test_debug_guard_steps_claim_only_taken_steps() {
+        let mut chacha = ChaCha20::new([0x42u8; 32], [0x24u8; 12], 0);
+        let mut message = [0u8; 300];
+        assert_eq!(
+            chacha.apply_keystream_steps(&mut message, 70).next(),
+            Some(70)
+        );
+        assert_eq!(chacha.position(), 70);
+        chacha.apply_keystream(&mut [0u8; 10]);
+        assert_eq!(chacha.position(), 80);
+    }
+
+    #[test]
+    #[cfg(feature = "debug_guards")]
+    fn test_debug_guard_reset() {
+        let mut chacha = ChaCha20::new([0x42u8; 32], [0x24u8; 12], 64);
+        let mut message = *b"hello";
+        chacha.apply_keystream(&mut message);
+        chacha.reset();
+        chacha.apply_keystream(&mut message);
+        assert_eq!(&message, b"hello");
+    }
+
+    #[test]
+    #[cfg(feature = "debug_guards")]
+    fn test_debug_guard_forward() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let mut chacha = ChaCha20::new(key, nonce, 64);
+        chacha.apply_keystream(&mut [0u8; 100]);
+        chacha.apply_keystream(&mut [0u8; 100]);
+        chacha.seek(0);
+        chacha.apply_keystream(&mut [0u8; 64]);
+        chacha.block(100);
+        chacha.apply_keystream(&mut [0u8; 0]);
+        chacha.apply_keystream(&mut [0u8; 1]);
+    }
+
+    #[test]
+    #[cfg(feature = "constant-path")]
+    fn test_constant_path_matches_reference() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        for seek in [0u64, 1, 63, 64, 200] {
+            let mut ours = [0u8; 64];
+            ChaCha20::new(key, nonce, seek).apply_keystream(&mut ours);
+            let mut cipher = rustcrypto_chacha20::ChaCha20::new_from_slices(&key, &nonce)
+                .expect("Valid keys and nonce.");
+            let mut theirs = [0u8; 64];
+            cipher.seek(seek);
+            cipher.apply_keystream(&mut theirs);
+            assert_eq!(ours, theirs);
+        }
+    }
+
+    #[test]
+    fn test_peek_block() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let mut chacha = ChaCha20::new(key, nonce, 42);
+        assert_eq!(chacha.position(), 42);
+        let first = chacha.peek_block(5);
+        let second = chacha.peek_block(5);
+        assert_eq!(first, second);
+        assert_eq!(chacha.position(), 42);
+        assert_eq!(chacha.get_keystream(5), first);
+        assert_eq!(chacha.position(), 5 * 64);
+    }
+
+    #[test]
+    #[cfg(feature = "profiling")]
+    fn test_block_computations() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let mut chacha = ChaCha20::new(key, nonce, 0);
+        assert_eq!(chacha.block_computations(), 0);
+        let (per_block, unaligned) = if cfg!(feature = "constant-path") {
+            (2, 4)
+        } else {
+            (1, 3)
+        };
+        chacha.apply_keystream(&mut [0u8; 128]);
+        assert_eq!(chacha.block_computations(), 2 * per_block);
+        let _ = chacha.peek_block(7);
+        assert_eq!(chacha.block_computations(), 2 * per_block);
+        chacha.seek(129);
+        chacha.apply_keystream(&mut [0u8; 128]);
+        assert_eq!(chacha.block_computations(), 2 * per_block + unaligned);
+        chacha.reset();
+        assert_eq!(chacha.block_computations(), 0);
+    }
+
+    #[test]
+    fn test_counter_offset_partial_block() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let seek = 3 * 64 + 17;
+        let message: Vec<u8> = (0..100).map(|i| i as u8).collect();
+        let mut ours = message.clone();
+        let mut chacha = ChaCha20::new(key, nonce, seek);
+        chacha.apply_keystream(&mut ours);
+        let mut cipher = rustcrypto_chacha20::ChaCha20::new_from_slices(&key, &nonce)
+            .expect("Valid keys and nonce.");
+        let mut theirs = message;
+        cipher.seek(seek);
+        cipher.apply_keystream(&mut theirs);
+        assert_eq!(ours, theirs);
+    }
+
+    #[test]
+    fn test_decrypt() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let plaintext = *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let mut chacha = ChaCha20::new(key, nonce, 64);
+        let mut ciphertext = [0u8; 114];
+        chacha.encrypt(&plaintext, &mut ciphertext).unwrap();
+        assert_ne!(ciphertext, plaintext);
+        ChaCha20::new(key, nonce, 64).decrypt(&mut ciphertext);
+        assert_eq!(ciphertext, plaintext);
+    }
+
+    #[test]
+    fn test_save_and_restore_position() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let plaintext = *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let mut chacha = ChaCha20::new(key, nonce, 64);
+        chacha.apply_keystream(&mut [0u8; 7]);
+        let saved = chacha.save_position();
+        assert_eq!(
+            saved,
+            CipherPosition {
+                block: 1,
+                offset: 7
+            }
+        );
+        let mut message = plaintext;
+        chacha.apply_keystream(&mut message);
+        let mut decryptor = ChaCha20::new(key, nonce, 0);
+        decryptor.restore_position(saved);
+        assert_eq!(decryptor.position(), 71);
+        decryptor.apply_keystream(&mut message);
+        assert_eq!(message, plaintext);
+    }
+
+    #[test]
+    fn test_seek_returning() {
+        let key = [0x42u8; 32];
+        let nonce = [0x24u8; 12];
+        let mut expected = [0u8; 100];
+        ChaCha20::new(key, nonce, 77).fill_keystream(&mut expected);
+        let mut chacha = ChaCha20::new(key, nonce, 77);
+        let previous = chacha.seek_returning(1000);
+        assert_eq!(
+            previous,
+            CipherPosition {
+                block: 1,
+                offset: 13
             }
+        );
+        chacha.fill_keystream(&mut [0u8; 10]);
+        chacha.restore_position(previous);
+        let mut restored = [0u8; 100];
+        chacha.fill_keystream(&mut restored);
+        assert_eq!(restored, expected);
+    }
+
+    #[test]
+    fn test_reset() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let mut chacha = ChaCha20::new(key, nonce, 42);
+        let mut binding = *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let to = binding.as_mut_slice();
+        chacha.apply_keystream(to);
+        chacha.block(9);
+        chacha.reset();
+        assert_eq!(chacha.position(), 42);
+        chacha.apply_keystream(to);
+        let binding = *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        assert_eq!(binding, to);
+    }
+
+    #[test]
+    #[cfg(feature = "ffi")]
+    #[allow(unsafe_code)]
+    fn test_ffi_round_trip() {
+        use ffi::*;
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let plaintext = *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let mut buffer = plaintext;
+        unsafe {
+            let encryptor = chacha20_new(key.as_ptr(), nonce.as_ptr(), 0);
+            assert!(!encryptor.is_null());
+            assert_eq!(chacha20_seek(encryptor, 64), CHACHA20_OK);
+            assert_eq!(
+                chacha20_apply(encryptor, buffer.as_mut_ptr(), buffer.len()),
+                CHACHA20_OK
+            );
+            chacha20_free(encryptor);
+            assert_eq!(buffer.as_slice(), hex::decode("6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0bf91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d807ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab77937365af90bbf74a35be6b40b8eedf2785e42874d").unwrap());
+            let decryptor = chacha20_new(key.as_ptr(), nonce.as_ptr(), 64);
+            assert_eq!(
+                chacha20_apply(decryptor, buffer.as_mut_ptr(), buffer.len()),
+                CHACHA20_OK
+            );
+            assert_eq!(
+                chacha20_apply(decryptor, std::ptr::null_mut(), 0),
+                CHACHA20_OK
+            );
+            assert_eq!(
+                chacha20_apply(decryptor, std::ptr::null_mut(), 1),
+                CHACHA20_ERR_NULL
+            );
+            assert_eq!(chacha20_seek(decryptor, u64::MAX), CHACHA20_ERR_OVERFLOW);
+            assert_eq!(chacha20_seek(decryptor, u32::MAX as u64 * 64), CHACHA20_OK);
+            assert_eq!(
+                chacha20_apply(decryptor, buffer.as_mut_ptr(), buffer.len()),
+                CHACHA20_ERR_OVERFLOW
+            );
+            chacha20_free(decryptor);
+            chacha20_free(std::ptr::null_mut());
+            assert!(chacha20_new(std::ptr::null(), nonce.as_ptr(), 0).is_null());
+        }
+        assert_eq!(buffer, plaintext);
+    }
+
+    #[test]
+    fn test_with_block_and_seek() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let mut fluent = ChaCha20::new(key, nonce, 0).with_block(5);
+        let mut setter = ChaCha20::new(key, nonce, 0);
+        setter.block(5);
+        let (mut a, mut b) = ([0u8; 100], [0u8; 100]);
+        fluent.apply_keystream(&mut a);
+        setter.apply_keystream(&mut b);
+        assert_eq!(a, b);
+        let mut fluent = ChaCha20::new(key, nonce, 0).with_seek(330);
+        let mut setter = ChaCha20::new(key, nonce, 0);
+        setter.seek(330);
+        assert_eq!(fluent.position(), 330);
+        fluent.apply_keystream(&mut a);
+        setter.apply_keystream(&mut b);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_reset_after_with_block_and_seek() {
+        let key = [0x42u8; 32];
+        let nonce = [0x24u8; 12];
+        let mut fluent = ChaCha20::new(key, nonce, 0).with_block(5);
+        fluent.seek(1000);
+        fluent.reset();
+        assert_eq!(fluent.position(), 5 * 64);
+        assert_eq!(fluent, ChaCha20::new_from_block(key, nonce, 5));
+        let mut fluent = ChaCha20::new(key, nonce, 0).with_seek(330);
+        fluent.block(9);
+        fluent.reset();
+        assert_eq!(fluent.position(), 330);
+        assert_eq!(fluent, ChaCha20::new(key, nonce, 330));
+    }
+
+    #[test]
+    fn test_apply_keystream_steps() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let message: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let mut single = message.clone();
+        let mut chacha = ChaCha20::new(key, nonce, 64);
+        chacha.apply_keystream(&mut single);
+        let mut stepped = message.clone();
+        let mut stepper = ChaCha20::new(key, nonce, 64);
+        let steps: Vec<usize> = stepper.apply_keystream_steps(&mut stepped, 70).collect();
+        assert_eq!(steps, [70, 70, 70, 70, 20]);
+        assert_eq!(single, stepped);
+        assert_eq!(stepper, chacha);
+        let mut partial = message;
+        let mut stepper = ChaCha20::new(key, nonce, 64);
+        assert_eq!(
+            stepper.apply_keystream_steps(&mut partial, 70).next(),
+            Some(70)
+        );
+        assert_eq!(stepper.stream.inner, 2);
+        assert_eq!(stepper.stream.seek, 6);
+        assert_eq!(partial[..70], single[..70]);
+    }
+
+    #[test]
+    fn test_seek_block_and_offset() {
+        let key = [0u8; 32];
+        let nonce = [0u8; 12];
+        let mut chacha = ChaCha20::new(key, nonce, 0);
+        chacha.seek_block_and_offset(3, 17);
+        assert_eq!(chacha.position(), 3 * 64 + 17);
+        assert_eq!(chacha.stream, ChaCha20::new(key, nonce, 3 * 64 + 17).stream);
+        let position = chacha.position();
+        chacha.seek_block_and_offset((position / 64) as u32, (position % 64) as usize);
+        assert_eq!(chacha.position(), position);
+        chacha.seek_block_and_offset(u32::MAX, 63);
+        assert_eq!(chacha.position(), u32::MAX as u64 * 64 + 63);
+    }
+
+    #[test]
+    fn test_exhausted_round_trips() {
+        let key = [0x42u8; 32];
+        let nonce = [0x24u8; 12];
+        let mut chacha = ChaCha20::new(key, nonce, KEYSTREAM_LEN - 10);
+        chacha.apply_keystream(&mut [0u8; 10]);
+        assert!(chacha.is_exhausted());
+        let position = chacha.save_position();
+        assert_eq!(
+            position,
+            CipherPosition {
+                block: u32::MAX,
+                offset: 64
+            }
+        );
+        let mut restored = ChaCha20::new(key, nonce, 0);
+        restored.restore_position(position);
+        assert!(restored.is_exhausted());
+        let from_bytes = ChaCha20::from_bytes(&chacha.to_bytes()).unwrap();
+        assert!(from_bytes.is_exhausted());
+        assert_eq!(from_bytes.to_bytes(), chacha.to_bytes());
+        let mut seeked = ChaCha20::new(key, nonce, 0);
+        seeked.seek(KEYSTREAM_LEN);
+        assert!(seeked.is_exhausted());
+        assert_eq!(seeked.save_position(), position);
+        assert!(ChaCha20::new(key, nonce, KEYSTREAM_LEN).is_exhausted());
+    }
+
+    #[test]
+    #[should_panic(expected = "Seek within the 32-bit block counter.")]
+    fn test_seek_past_end() {
+        ChaCha20::new([0u8; 32], [0u8; 12], 0).seek(KEYSTREAM_LEN + 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Offset within a block.")]
+    fn test_seek_block_and_offset_past_end() {
+        let mut chacha = ChaCha20::new([0u8; 32], [0u8; 12], 0);
+        chacha.seek_block_and_offset(u32::MAX - 1, 64);
+    }
+
+    #[test]
+    #[should_panic(expected = "Offset within a block.")]
+    fn test_seek_block_and_offset_out_of_block() {
+        let mut chacha = ChaCha20::new([0u8; 32], [0u8; 12], 0);
+        chacha.seek_block_and_offset(0, 64);
+    }
+
+    #[test]
+    fn test_nonce_from_counter() {
+        // WireGuard transport messages use a zero salt and the little-endian message counter.
+        assert_eq!(
+            nonce_from_counter([0; 4], 1),
+            [0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]
+        );
+        let nonce = nonce_from_counter([0xde, 0xad, 0xbe, 0xef], 0x0102030405060708);
+        assert_eq!(nonce, hex::decode("deadbeef0807060504030201").unwrap()[..]);
+    }
+
+    #[test]
+    fn test_apply_keystream_conditional() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let message: Vec<u8> = (0..100).map(|i| i as u8).collect();
+        let mut expected = message.clone();
+        ChaCha20::new(key, nonce, 3).apply_keystream(&mut expected);
+        let mut applied = message.clone();
+        let mut yes = ChaCha20::new(key, nonce, 3);
+        yes.apply_keystream_conditional(&mut applied, true);
+        assert_eq!(applied, expected);
+        let mut skipped = message.clone();
+        let mut no = ChaCha20::new(key, nonce, 3);
+        no.apply_keystream_conditional(&mut skipped, false);
+        assert_eq!(skipped, message);
+        assert_eq!(no, yes);
+        assert_eq!(no.position(), 103);
+    }
+
+    #[test]
+    fn test_apply_keystream_counted() {
+        let key = [0u8; 32];
+        let nonce = [0u8; 12];
+        let mut message = [0u8; 150];
+        let mut chacha = ChaCha20::new(key, nonce, 0);
+        let (head, rest) = message.split_at_mut(3);
+        let (middle, tail) = rest.split_at_mut(64);
+        let counted = chacha.apply_keystream_counted(head)
+            + chacha.apply_keystream_counted(middle)
+            + chacha.apply_keystream_counted(tail);
+        assert_eq!(counted, 150);
+        assert_eq!(chacha.position(), 150);
+        assert_eq!(chacha.apply_keystream_counted(&mut []), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "getrandom")]
+    fn test_random_nonce() {
+        let first = random_nonce().unwrap();
+        let second = random_nonce().unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_block_counter() {
+        let chacha = ChaCha20::new([0u8; 32], [0u8; 12], 128);
+        assert_eq!(chacha.block_counter(), 2);
+        assert_eq!(chacha.position(), 128);
+        let chacha = ChaCha20::new([0u8; 32], [0u8; 12], 130);
+        assert_eq!(chacha.block_counter(), 2);
+        assert_eq!(chacha.position(), 130);
+    }
+
+    #[test]
+    fn test_keystream_block_iter() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let mut chacha = ChaCha20::new(key, nonce, 64);
+        let blocks: Vec<[u8; 64]> = chacha.keystream_block_iter().take(3).collect();
+        assert_eq!(chacha.block_counter(), 4);
+        let mut expected = ChaCha20::new(key, nonce, 0);
+        for (n, block) in (1..4).zip(blocks.iter()) {
+            assert_eq!(*block, expected.get_keystream(n));
+        }
+        let mut end = ChaCha20::new(key, nonce, 0).with_block(u32::MAX - 1);
+        assert_eq!(end.keystream_block_iter().count(), 2);
+        assert_eq!(end.block_counter(), u32::MAX);
+        assert!(end.is_exhausted());
+        assert_eq!(end.keystream_block_iter().next(), None);
+    }
+
+    #[test]
+    fn test_keystream_block_iter_unaligned_last_block() {
+        let key = [0x42u8; 32];
+        let nonce = [0x24u8; 12];
+        let mut chacha = ChaCha20::new(key, nonce, u32::MAX as u64 * 64 + 17);
+        assert_eq!(chacha.keystream_block_iter().next(), None);
+        assert_eq!(chacha.position(), u32::MAX as u64 * 64 + 17);
+        let mut chacha = ChaCha20::new(key, nonce, (u32::MAX as u64 - 1) * 64 + 17);
+        let blocks: Vec<[u8; 64]> = chacha.keystream_block_iter().collect();
+        assert_eq!(blocks.len(), 1);
+        let mut expected = [0u8; 64];
+        ChaCha20::new(key, nonce, (u32::MAX as u64 - 1) * 64 + 17).apply_keystream(&mut expected);
+        assert_eq!(blocks[0], expected);
+        assert_eq!(chacha.position(), u32::MAX as u64 * 64 + 17);
+    }
+
+    #[test]
+    fn test_apply_keystream_xor() {
+        let message: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let mut expected = message.clone();
+        let mut first = ChaCha20::new([1u8; 32], [2u8; 12], 5);
+        let mut second = ChaCha20::new([3u8; 32], [4u8; 12], 70);
+        first.apply_keystream(&mut expected);
+        second.apply_keystream(&mut expected);
+        let mut combined = message;
+        let mut a = ChaCha20::new([1u8; 32], [2u8; 12], 5);
+        let mut b = ChaCha20::new([3u8; 32], [4u8; 12], 70);
+        a.apply_keystream_xor(&mut b, &mut combined);
+        assert_eq!(combined, expected);
+        assert_eq!(a, first);
+        assert_eq!(b, second);
+    }
+
+    #[test]
+    fn test_new_personalized() {
+        let key = [0x42u8; 32];
+        let nonce = [0x24u8; 12];
+        let keystream = |mut chacha: ChaCha20| {
+            let mut out = vec![0u8; 4096];
+            chacha.apply_keystream(&mut out);
+            out
+        };
+        let plain = keystream(ChaCha20::new(key, nonce, 0));
+        let first = keystream(ChaCha20::new_personalized(
+            key,
+            nonce,
+            *b"subsystem-one---",
+            0,
+        ));
+        let second = keystream(ChaCha20::new_personalized(
+            key,
+            nonce,
+            *b"subsystem-two---",
+            0,
+        ));
+        assert_eq!(
+            first,
+            keystream(ChaCha20::new(
+                derive_subkey(key, *b"subsystem-one---"),
+                nonce,
+                0
+            ))
+        );
+        for (a, b) in [(&first, &second), (&first, &plain), (&second, &plain)] {
+            let equal = a.iter().zip(b.iter()).filter(|(x, y)| x == y).count();
+            assert!(equal < 64, "{} of 4096 bytes agree", equal);
+        }
+    }
+
+    #[test]
+    fn test_with_nonce() {
+        let key = [0x42u8; 32];
+        let mut chacha = ChaCha20::new(key, [0x24u8; 12], 100);
+        chacha.apply_keystream(&mut [0u8; 10]);
+        let sibling = chacha.with_nonce([0x99u8; 12]);
+        assert_eq!(sibling, ChaCha20::new(key, [0x99u8; 12], 0));
+        assert_eq!(sibling.stream.key, key);
+        assert_eq!(chacha.position(), 110);
+        let big = ChaCha20::new_be(key, [0x24u8; 12], 0);
+        assert_eq!(
+            big.with_nonce([7u8; 12]),
+            ChaCha20::new_be(key, [7u8; 12], 0)
+        );
+    }
+
+    #[test]
+    fn test_with_nonce_messages_decrypt_independently() {
+        let session = ChaCha20::new([0x42u8; 32], [0u8; 12], 0);
+        let first_nonce = nonce_from_counter([0; 4], 1);
+        let second_nonce = nonce_from_counter([0; 4], 2);
+        let first = *b"first message under the session key";
+        let second = *b"second message under the same key!!";
+        let mut first_ct = first;
+        session
+            .with_nonce(first_nonce)
+            .apply_keystream(&mut first_ct);
+        let mut second_ct = second;
+        session
+            .with_nonce(second_nonce)
+            .apply_keystream(&mut second_ct);
+        assert_ne!(xor_of(&first_ct, &second_ct), xor_of(&first, &second));
+        session.with_nonce(second_nonce).decrypt(&mut second_ct);
+        assert_eq!(second_ct, second);
+        session.with_nonce(first_nonce).decrypt(&mut first_ct);
+        assert_eq!(first_ct, first);
+    }
+
+    fn xor_of(a: &[u8], b: &[u8]) -> Vec<u8> {
+        a.iter().zip(b.iter()).map(|(x, y)| x ^ y).collect()
+    }
+
+    #[test]
+    fn test_feed() {
+        let mut rng = StdRng::seed_from_u64(FUZZ_SEED);
+        let key: [u8; 32] = rng.gen();
+        let nonce: [u8; 12] = rng.gen();
+        let message = gen_garbage(&mut rng, 200);
+        let mut expected = message.clone();
+        ChaCha20::new(key, nonce, 30).apply_keystream(&mut expected);
+        let mut chacha = ChaCha20::new(key, nonce, 30);
+        let fed: Vec<u8> = message.iter().map(|&byte| chacha.feed(byte)).collect();
+        assert_eq!(fed, expected);
+        assert_eq!(chacha.position(), 230);
+    }
+
+    #[test]
+    fn test_try_from_slice() {
+        let mut bytes = [0u8; 45];
+        bytes[..32].copy_from_slice(&[0x42u8; 32]);
+        bytes[32..44].copy_from_slice(&[0x24u8; 12]);
+        let chacha = ChaCha20::try_from(&bytes[..44]).unwrap();
+        assert_eq!(chacha, ChaCha20::new([0x42u8; 32], [0x24u8; 12], 0));
+        assert_eq!(
+            ChaCha20::try_from(&bytes[..43]).unwrap_err(),
+            ChaChaError::InvalidKeyNonceLength
+        );
+        assert_eq!(
+            ChaCha20::try_from(&bytes[..]).unwrap_err(),
+            ChaChaError::InvalidKeyNonceLength
+        );
+    }
+
+    #[test]
+    fn test_error_display() {
+        let cases = [
+            (
+                ChaChaError::LengthMismatch,
+                "input and output lengths differ",
+            ),
+            (ChaChaError::InvalidKeyLength, "key must be 32 bytes"),
+            (ChaChaError::InvalidNonceLength, "nonce must be 12 bytes"),
+            (
+                ChaChaError::InvalidKeyNonceLength,
+                "key and nonce must be 44 bytes",
+            ),
+            (ChaChaError::AuthenticationFailed, "authentication failed"),
+            (
+                ChaChaError::InvalidPosition,
+                "offset must be within a block",
+            ),
+        ];
+        for (error, message) in cases {
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_weak_input_display() {
+        let cases = [
+            (
+                WeakInput::TestVectorKey,
+                "key is the RFC 7539 test vector key",
+            ),
+            (WeakInput::ZeroKey, "key is all zeros"),
+            (WeakInput::ZeroNonce, "nonce is all zeros"),
+        ];
+        for (error, message) in cases {
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_derive_subkey() {
+        // HChaCha20 test vector, draft-irtf-cfrg-xchacha section 2.2.1.
+        let master =
+            hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+                .unwrap();
+        let master: [u8; 32] = master.try_into().unwrap();
+        let context = hex::decode("000000090000004a0000000031415927").unwrap();
+        let context: [u8; 16] = context.try_into().unwrap();
+        let subkey = derive_subkey(master, context);
+        assert_eq!(
+            hex::encode(subkey),
+            "82413b4227b27bfed30e42508a877d73a0f9e4d58a74a853c12ec41326d3ecdc"
+        );
+        assert_eq!(derive_subkey(master, context), subkey);
+        let mut other = context;
+        other[15] ^= 1;
+        assert_ne!(derive_subkey(master, other), subkey);
+    }
+
+    #[test]
+    fn test_new_checked() {
+        assert_eq!(
+            ChaCha20::new_checked([0u8; 32], [1u8; 12], 0).unwrap_err(),
+            WeakInput::ZeroKey
+        );
+        let chacha = ChaCha20::new_checked([7u8; 32], [0u8; 12], 5).unwrap();
+        assert_eq!(chacha, ChaCha20::new([7u8; 32], [0u8; 12], 5));
+        assert!(ChaCha20::new_checked(RFC_TEST_KEY, [0u8; 12], 0).is_ok());
+    }
+
+    #[test]
+    fn test_apply_keystream_chunks() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let message: Vec<u8> = (0..250).map(|i| i as u8).collect();
+        let mut contiguous = message.clone();
+        let mut chacha = ChaCha20::new(key, nonce, 10);
+        chacha.apply_keystream(&mut contiguous);
+        let mut chunked = message;
+        let mut chunker = ChaCha20::new(key, nonce, 10);
+        let (a, rest) = chunked.split_at_mut(3);
+        let (b, rest) = rest.split_at_mut(61);
+        let (c, rest) = rest.split_at_mut(65);
+        let (d, e) = rest.split_at_mut(1);
+        chunker.apply_keystream_chunks(vec![a, b, c, d, e].into_iter());
+        assert_eq!(chunked, contiguous);
+        assert_eq!(chunker, chacha);
+    }
+
+    #[test]
+    fn test_new_from_block_matches_new() {
+        let key = [0x42u8; 32];
+        let nonce = [0x24u8; 12];
+        for block in [0, 1, 2, 1 << 16, u32::MAX / 64, u32::MAX - 1, u32::MAX] {
+            let from_block = ChaCha20::new_from_block(key, nonce, block);
+            let from_seek = ChaCha20::new(key, nonce, block as u64 * 64);
+            assert_eq!(from_block, from_seek);
+            assert_eq!(from_block.position(), block as u64 * 64);
+            assert_eq!(from_block.peek_block(block), from_seek.peek_block(block));
+        }
+    }
+
+    #[test]
+    fn test_fill_keystream_u32() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let mut bytes = [0u8; 100 * 4];
+        let mut chacha = ChaCha20::new(key, nonce, 64);
+        chacha.fill_keystream(&mut bytes);
+        let mut words = [0u32; 100];
+        let mut worded = ChaCha20::new(key, nonce, 64);
+        worded.fill_keystream_u32(&mut words);
+        let reinterpreted: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        assert_eq!(reinterpreted, bytes);
+        assert_eq!(worded, chacha);
+        let mut state = [0u32; 16];
+        ChaCha20::new(key, nonce, 0)
+            .with_block(1)
+            .fill_keystream_u32(&mut state);
+        let mut expected = crate::core::state_words(key, nonce, 1);
+        chacha_block(&mut expected);
+        assert_eq!(state, expected);
+    }
+
+    #[test]
+    fn test_get_keystream_last_block() {
+        let key = [0x42u8; 32];
+        let nonce = [0x24u8; 12];
+        let mut chacha = ChaCha20::new(key, nonce, 0);
+        let last = chacha.get_keystream(u32::MAX);
+        assert_eq!(chacha.block_counter(), u32::MAX);
+        let state = crate::core::block(&crate::core::state_words(key, nonce, u32::MAX));
+        let expected: Vec<u8> = state.iter().flat_map(|w| w.to_le_bytes()).collect();
+        assert_eq!(last[..], expected[..]);
+    }
+
+    #[test]
+    fn test_clone_at() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let original = ChaCha20::new(key, nonce, 100);
+        let mut fork = original.clone_at(3);
+        assert_eq!(fork.get_keystream(3), original.peek_block(3));
+        assert_eq!(original.position(), 100);
+        let be = ChaCha20::new_be(key, nonce, 0);
+        assert_eq!(be.clone_at(2).peek_block(2), be.peek_block(2));
+    }
+
+    #[test]
+    fn test_to_and_from_bytes() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let mut chacha = ChaCha20::new(key, nonce, 64);
+        chacha.apply_keystream(&mut [0u8; 70]);
+        let bytes = chacha.to_bytes();
+        assert_eq!(bytes[..32], key);
+        assert_eq!(bytes[32..44], nonce);
+        assert_eq!(bytes[44..], [2, 0, 0, 0, 6]);
+        let restored = ChaCha20::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.position(), chacha.position());
+        assert_eq!(restored.to_bytes(), bytes);
+        assert_eq!(restored, ChaCha20::new(key, nonce, 134));
+        let be = ChaCha20::new_be(key, nonce, 134);
+        let bytes = be.to_bytes();
+        assert_eq!(bytes[48], 0x80 | 6);
+        assert_eq!(ChaCha20::from_bytes(&bytes).unwrap(), be);
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_bad_offset() {
+        let mut bytes = ChaCha20::new([0x42u8; 32], [0x24u8; 12], 0).to_bytes();
+        bytes[48] = 64;
+        assert_eq!(
+            ChaCha20::from_bytes(&bytes),
+            Err(ChaChaError::InvalidPosition)
+        );
+        bytes[48] = 0xff;
+        assert_eq!(
+            ChaCha20::from_bytes(&bytes),
+            Err(ChaChaError::InvalidPosition)
+        );
+        bytes[44..48].copy_from_slice(&u32::MAX.to_le_bytes());
+        bytes[48] = 65;
+        assert_eq!(
+            ChaCha20::from_bytes(&bytes),
+            Err(ChaChaError::InvalidPosition)
+        );
+        bytes[48] = 64;
+        assert!(ChaCha20::from_bytes(&bytes).unwrap().is_exhausted());
+    }
+
+    #[test]
+    fn test_keyed_digest() {
+        let key = [0x42u8; 32];
+        let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let digest = keyed_digest(key, &data);
+        assert_eq!(keyed_digest(key, &data), digest);
+        assert_ne!(keyed_digest([0x43u8; 32], &data), digest);
+        assert_ne!(keyed_digest(key, &data[..199]), digest);
+        assert_ne!(keyed_digest(key, &[0u8; 3]), keyed_digest(key, &[0u8; 4]));
+    }
+
+    #[test]
+    fn test_rekey() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let mut chacha = ChaCha20::new(key, nonce, 10);
+        let mut new_key = [0u8; 32];
+        ChaCha20::new(key, nonce, 10).fill_keystream(&mut new_key);
+        chacha.rekey();
+        assert_eq!(chacha.position(), 0);
+        assert_eq!(chacha.stream.key, new_key);
+        let mut after = [0u8; 64];
+        chacha.fill_keystream(&mut after);
+        assert_eq!(after, ChaCha20::new(new_key, nonce, 0).peek_block(0));
+        let mut continued = [0u8; 64];
+        ChaCha20::new(key, nonce, 42).fill_keystream(&mut continued);
+        assert_ne!(after, continued);
+        assert_ne!(after, ChaCha20::new(key, nonce, 0).peek_block(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Keystream within the 32-bit block counter.")]
+    fn test_rekey_past_end() {
+        ChaCha20::new([0x42u8; 32], [0x24u8; 12], KEYSTREAM_LEN - 31).rekey();
+    }
+
+    #[test]
+    fn test_keystream_range() {
+        let key = [0x42u8; 32];
+        let nonce = [0x24u8; 12];
+        for (start, len) in [(0, 64), (3, 1), (70, 500), (u32::MAX as u64 * 64 - 10, 10)] {
+            let mut ranged = vec![0u8; len];
+            keystream_range(key, nonce, start, &mut ranged);
+            let mut filled = vec![0u8; len];
+            ChaCha20::new(key, nonce, start).fill_keystream(&mut filled);
+            assert_eq!(ranged, filled);
+        }
+    }
+
+    #[test]
+    fn test_apply_keystream_empty() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let mut chacha = ChaCha20::new(key, nonce, 64);
+        assert!(chacha.apply_keystream(&mut []).is_empty());
+        assert_eq!(chacha.position(), 64);
+        assert_eq!(chacha, ChaCha20::new(key, nonce, 64));
+        let mut message = [0u8; 10];
+        chacha.apply_keystream(&mut message);
+        chacha.apply_keystream(&mut []);
+        assert_eq!(chacha.position(), 74);
+        let mut rest = [0u8; 10];
+        chacha.apply_keystream(&mut rest);
+        let mut expected = [0u8; 20];
+        ChaCha20::new(key, nonce, 64).apply_keystream(&mut expected);
+        assert_eq!(message, expected[..10]);
+        assert_eq!(rest, expected[10..]);
+    }
+
+    #[test]
+    fn test_rfc_keystream() {
+        // RFC 7539 section 2.4.2, the keystream for the sunscreen message from counter 1.
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let mut keystream = [0u8; 114];
+        ChaCha20::new_from_block(key, nonce, 1).fill_keystream(&mut keystream);
+        assert_eq!(keystream.to_vec(), hex::decode("224f51f3401bd9e12fde276fb8631ded8c131f823d2c06e27e4fcaec9ef3cf788a3b0aa372600a92b57974cded2b9334794cba40c63e34cdea212c4cf07d41b769a6749f3f630f4122cafe28ec4dc47e26d4346d70b98c73f3e9c53ac40c5945398b6eda1a832c89c167eacd901d7e2bf363").unwrap());
+        let mut plaintext = *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        for (p, k) in plaintext.iter_mut().zip(keystream.iter()) {
+            *p ^= k;
+        }
+        assert_eq!(plaintext.to_vec(), hex::decode("6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0bf91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d807ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab77937365af90bbf74a35be6b40b8eedf2785e42874d").unwrap());
+    }
+
+    #[test]
+    fn test_advance() {
+        let key = [0x42u8; 32];
+        let nonce = [0x24u8; 12];
+        let mut chacha = ChaCha20::new(key, nonce, 0);
+        chacha.advance(100);
+        assert_eq!(chacha.block_counter(), 1);
+        let mut advanced = [0u8; 80];
+        chacha.fill_keystream(&mut advanced);
+        let mut seeked = [0u8; 80];
+        ChaCha20::new(key, nonce, 0)
+            .with_seek(100)
+            .fill_keystream(&mut seeked);
+        assert_eq!(advanced, seeked);
+        chacha.advance(20);
+        assert_eq!(chacha.position(), 200);
+    }
+
+    #[test]
+    #[should_panic(expected = "Seek within the 32-bit block counter.")]
+    fn test_advance_overflow() {
+        let mut chacha = ChaCha20::new([0u8; 32], [0u8; 12], 0).with_block(u32::MAX);
+        chacha.advance(65);
+    }
+
+    #[test]
+    fn test_advance_to_end() {
+        let mut chacha = ChaCha20::new([0u8; 32], [0u8; 12], 0).with_block(u32::MAX);
+        chacha.advance(64);
+        assert!(chacha.is_exhausted());
+        assert_eq!(chacha.position(), KEYSTREAM_LEN);
+        chacha.advance(0);
+        assert!(chacha.is_exhausted());
+        let mut unaligned = ChaCha20::new([0u8; 32], [0u8; 12], 10);
+        unaligned.advance(KEYSTREAM_LEN - 10);
+        assert!(unaligned.is_exhausted());
+    }
+
+    #[test]
+    fn test_key_and_nonce_newtypes() {
+        let raw_key = [0x42u8; 32];
+        let raw_nonce = [0x24u8; 12];
+        let key = Key::from(raw_key);
+        let nonce = Nonce::from(raw_nonce);
+        assert_eq!(key.as_ref(), &raw_key[..]);
+        assert_eq!(nonce.as_ref(), &raw_nonce[..]);
+        assert_eq!(<[u8; 32]>::from(key), raw_key);
+        assert_eq!(<[u8; 12]>::from(nonce), raw_nonce);
+        assert_eq!(
+            ChaCha20::new(key, nonce, 5),
+            ChaCha20::new(raw_key, raw_nonce, 5)
+        );
+        assert_eq!(
+            ChaCha20::new(key, raw_nonce, 5),
+            ChaCha20::new(raw_key, nonce, 5)
+        );
+        assert!(!format!("{:?}", key).contains("66"));
+    }
+
+    #[test]
+    fn test_xor_blocks() {
+        let mut a = [0u8; 64];
+        let mut b = [0u8; 64];
+        for i in 0..64 {
+            a[i] = i as u8;
+            b[i] = 0xff - i as u8 * 2;
+        }
+        let xored = xor_blocks(&a, &b);
+        assert_eq!(xored[0], 0xff);
+        assert_eq!(xored[1], 0x01 ^ 0xfd);
+        assert_eq!(xored[63], 63 ^ (0xff - 126));
+        assert_eq!(xor_blocks(&xored, &b), a);
+        assert_eq!(xor_blocks(&a, &a), [0u8; 64]);
+        let chacha = ChaCha20::new([0x42u8; 32], [0x24u8; 12], 0);
+        let block = chacha.peek_block(0);
+        let mut applied = a;
+        ChaCha20::new([0x42u8; 32], [0x24u8; 12], 0).apply_keystream(&mut applied);
+        assert_eq!(xor_blocks(&a, &block), applied);
+    }
+
+    #[test]
+    fn test_counter_widths() {
+        use crate::core::{block, state_words_for, Djb, Ietf};
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000090000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let ietf = state_words_for::<Ietf>(key, nonce, 1);
+        assert_eq!(ietf, crate::core::state_words(key, nonce, 1));
+        assert_eq!(block(&ietf)[0], 0xe4e7f110);
+        // The all-zero DJB vector: key and nonce zero, counter 0.
+        let djb = block(&state_words_for::<Djb>([0; 32], [0; 8], 0));
+        let bytes: Vec<u8> = djb.iter().flat_map(|w| w.to_le_bytes()).collect();
+        assert_eq!(hex::encode(bytes), "76b8e0ada0f13d90405d6ae55386bd28bdd219b8a08ded1aa836efcc8b770dc7da41597c5157488d7724e03fb8d84a376a43b8f41518a11cc387b669b2ee6586");
+        // A 64-bit counter carries into word 13, where the IETF layout keeps its first nonce word.
+        let wide = state_words_for::<Djb>(key, [0xab; 8], (7 << 32) | 5);
+        assert_eq!(wide[12], 5);
+        assert_eq!(wide[13], 7);
+        assert_eq!(wide[14..], [0xabababab, 0xabababab]);
+        assert_eq!(wide[..12], ietf[..12]);
+    }
+
+    #[test]
+    fn test_chacha20_xor() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let plaintext = *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let mut one_shot = plaintext;
+        chacha20_xor(key, nonce, 1, &mut one_shot);
+        let mut expected = plaintext;
+        ChaCha20::new_from_block(key, nonce, 1).apply_keystream(&mut expected);
+        assert_eq!(one_shot, expected);
+        chacha20_xor(key, nonce, 1, &mut one_shot);
+        assert_eq!(one_shot, plaintext);
+    }
+
+    #[test]
+    fn test_keystream_block() {
+        let mut rng = StdRng::seed_from_u64(FUZZ_SEED);
+        let key: [u8; 32] = rng.gen();
+        let nonce: [u8; 12] = rng.gen();
+        let mut chacha = ChaCha20::new(key, nonce, 0);
+        for counter in [0, 1, rng.gen(), u32::MAX] {
+            assert_eq!(
+                keystream_block(key, nonce, counter),
+                chacha.get_keystream(counter)
+            );
+        }
+    }
+
+    #[test]
+    fn test_associated_constants() {
+        assert_eq!(ChaCha20::KEY_SIZE, 32);
+        assert_eq!(ChaCha20::NONCE_SIZE, 12);
+        assert_eq!(ChaCha20::BLOCK_SIZE, 64);
+        assert_eq!(ChaCha20::ROUNDS, 20);
+        assert_eq!(ChaCha20::KEY_SIZE, ::core::mem::size_of::<Key>());
+        assert_eq!(ChaCha20::NONCE_SIZE, ::core::mem::size_of::<Nonce>());
+    }
+
+    impl crate::StreamCipher for rustcrypto_chacha20::ChaCha20 {
+        const KEY_SIZE: usize = 32;
+        const NONCE_SIZE: usize = 12;
+        const BLOCK_SIZE: usize = 64;
+        const ROUNDS: usize = 20;
+
+        fn apply_keystream(&mut self, to: &mut [u8]) {
+            StreamCipher::apply_keystream(self, to);
+        }
+
+        fn seek(&mut self, seek: u64) {
+            StreamCipherSeek::seek(self, seek);
+        }
+
+        fn position(&self) -> u64 {
+            self.current_pos()
+        }
+    }
+
+    fn encrypt_at<C: crate::StreamCipher>(cipher: &mut C, seek: u64, to: &mut [u8]) {
+        assert_eq!(C::KEY_SIZE, 32);
+        assert_eq!(C::BLOCK_SIZE, 64);
+        cipher.seek(seek);
+        cipher.apply_keystream(to);
+        assert_eq!(cipher.position(), seek + to.len() as u64);
+    }
+
+    #[test]
+    fn test_generic_stream_cipher() {
+        let mut rng = StdRng::seed_from_u64(FUZZ_SEED);
+        let key: [u8; 32] = rng.gen();
+        let nonce: [u8; 12] = rng.gen();
+        let mut ours = ChaCha20::new(key, nonce, 0);
+        let mut theirs = rustcrypto_chacha20::ChaCha20::new(&key.into(), &nonce.into());
+        let mut seek = 0;
+        for _ in 0..16 {
+            seek += rng.gen_range(0..1 << 16);
+            let plaintext = gen_garbage(&mut rng, 300);
+            let mut a = plaintext.clone();
+            let mut b = plaintext;
+            encrypt_at(&mut ours, seek, &mut a);
+            encrypt_at(&mut theirs, seek, &mut b);
+            assert_eq!(a, b);
+            seek += 300;
+        }
+    }
+
+    #[test]
+    fn test_is_exhausted() {
+        let mut chacha = ChaCha20::new([0x42u8; 32], [0x24u8; 12], u32::MAX as u64 * 64 + 60);
+        assert!(!chacha.is_exhausted());
+        assert_eq!(chacha.remaining_blocks(), 1);
+        chacha.apply_keystream(&mut [0u8; 3]);
+        assert!(!chacha.is_exhausted());
+        let mut last = [0u8; 1];
+        chacha.apply_keystream(&mut last);
+        assert!(chacha.is_exhausted());
+        assert_eq!(chacha.remaining_blocks(), 0);
+        assert_eq!(chacha.position(), (u32::MAX as u64 + 1) * 64);
+        assert_eq!(last[0], chacha.peek_block(u32::MAX)[63]);
+        chacha.apply_keystream(&mut []);
+        assert!(chacha.is_exhausted());
+        let mut whole = ChaCha20::new([0x42u8; 32], [0x24u8; 12], 0).with_block(u32::MAX);
+        whole.apply_keystream(&mut [0u8; 64]);
+        assert!(whole.is_exhausted());
+    }
+
+    #[test]
+    fn test_unaligned_window_at_last_block() {
+        let key = [0x42u8; 32];
+        let nonce = [0x24u8; 12];
+        let last = keystream_block(key, nonce, u32::MAX);
+        let seek = 17;
+        let window = cipher::keystream_at_slice(key, nonce, u32::MAX, seek, ByteOrder::Little);
+        assert_eq!(window[..64 - seek], last[seek..]);
+        assert_eq!(window[64 - seek..], [0u8; 17]);
+        let mut stream = KeyStream::new(key, nonce, u32::MAX as u64 * 64 + seek as u64);
+        assert_eq!(stream.current(), window);
+        let mut chacha = ChaCha20::new(key, nonce, u32::MAX as u64 * 64 + seek as u64);
+        let mut tail = [0u8; 64 - 17];
+        chacha.apply_keystream(&mut tail);
+        assert_eq!(tail, last[seek..]);
+        assert!(chacha.is_exhausted());
+    }
+
+    #[test]
+    #[should_panic(expected = "Keystream within the 32-bit block counter.")]
+    fn test_apply_keystream_past_end() {
+        let mut chacha = ChaCha20::new([0x42u8; 32], [0x24u8; 12], u32::MAX as u64 * 64);
+        chacha.apply_keystream(&mut [0u8; 100]);
+    }
+
+    #[test]
+    fn test_past_end_leaves_buffer_unchanged() {
+        use ::std::panic::{catch_unwind, AssertUnwindSafe};
+        let start = u32::MAX as u64 * 64;
+        let message: Vec<u8> = (0..100).map(|i| i as u8).collect();
+        let entry_points: [fn(&mut ChaCha20, &mut [u8]); 5] = [
+            |chacha, buf| {
+                chacha.apply_keystream(buf);
+            },
+            |chacha, buf| chacha.apply_keystream_conditional(buf, true),
+            |chacha, buf| chacha.apply_keystream_vectored(&mut [buf]),
+            |chacha, buf| {
+                let len = buf.len();
+                chacha.apply_keystream_wrapping(buf, 0, len)
+            },
+            |chacha, buf| chacha.fill_keystream(buf),
+        ];
+        for apply in entry_points.iter() {
+            let mut chacha = ChaCha20::new([0x42u8; 32], [0x24u8; 12], start);
+            let mut buffer = message.clone();
+            let result = catch_unwind(AssertUnwindSafe(|| apply(&mut chacha, &mut buffer)));
+            assert!(result.is_err());
+            assert_eq!(buffer, message);
+            assert_eq!(chacha.position(), start);
+        }
+        let mut chacha = ChaCha20::new([0x42u8; 32], [0x24u8; 12], start);
+        let mut other = ChaCha20::new([0x42u8; 32], [0x24u8; 12], 0);
+        let mut buffer = message.clone();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            other.apply_keystream_xor(&mut chacha, &mut buffer)
+        }));
+        assert!(result.is_err());
+        assert_eq!(buffer, message);
+        assert_eq!(other.position(), 0);
+    }
+
+    #[test]
+    fn test_debug_redacts_key() {
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = 0xe0 + i as u8;
+        }
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let chacha = ChaCha20::new(key, nonce, 64);
+        let debug = format!("{:?}", chacha);
+        assert!(debug.contains("<redacted>"));
+        assert!(debug.contains("inner: 1"));
+        for byte in key {
+            assert!(!debug.contains(&format!("{:02X}", byte)));
+            assert!(!debug.contains(&byte.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_split_apply_keystream() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let plaintext = hex::decode("4c616469657320616e642047656e746c656d656e206f662074686520636c617373206f66202739393a204966204920636f756c64206f6666657220796f75206f6e6c79206f6e652074697020666f7220746865206675747572652c2073756e73637265656e20776f756c642062652069742e").unwrap();
+        let mut one_shot = plaintext.clone();
+        let mut chacha = ChaCha20::new(key, nonce, 64);
+        chacha.apply_keystream(&mut one_shot);
+        let mut split = plaintext;
+        let mut stepper = ChaCha20::new(key, nonce, 64);
+        let (head, tail) = split.split_at_mut(3);
+        stepper.apply_keystream(head);
+        assert_eq!(stepper.position(), 67);
+        stepper.apply_keystream(tail);
+        assert_eq!(split, one_shot);
+        assert_eq!(stepper.position(), chacha.position());
+        assert_eq!(stepper, chacha);
+    }
+
+    #[test]
+    fn test_aligned_and_unaligned_keystream() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let mut cipher = rustcrypto_chacha20::ChaCha20::new_from_slices(&key, &nonce)
+            .expect("Valid keys and nonce.");
+        for seek in 0..=130u64 {
+            let mut ours = [0u8; 64];
+            ChaCha20::new(key, nonce, seek).apply_keystream(&mut ours);
+            let mut theirs = [0u8; 64];
+            cipher.seek(seek);
+            cipher.apply_keystream(&mut theirs);
+            assert_eq!(ours, theirs, "Keystream differs at seek {}", seek);
+        }
+        let aligned = ChaCha20::new(key, nonce, 0).peek_block(2);
+        assert_eq!(KeyStream::new(key, nonce, 128).current(), aligned);
+        let unaligned = KeyStream::new(key, nonce, 130).current();
+        assert_eq!(unaligned[..62], aligned[2..]);
+    }
+
+    #[test]
+    fn test_keystream_fill() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        for (seek, len) in [(0u64, 64usize), (64, 114), (17, 3), (209, 300)] {
+            let mut stream = KeyStream::new(key, nonce, seek);
+            let mut filled = vec![0xff; len];
+            stream.fill(&mut filled);
+            let mut chacha = ChaCha20::new(key, nonce, seek);
+            let mut xored = vec![0u8; len];
+            chacha.apply_keystream(&mut xored);
+            assert_eq!(filled, xored);
+            assert_eq!(stream.position(), chacha.position());
+        }
+        let mut stream = KeyStream::new_from_block(key, nonce, 1);
+        let first = stream.next_block();
+        assert_eq!(first, ChaCha20::new(key, nonce, 0).peek_block(1));
+        assert_eq!(stream.position(), 128);
+    }
+
+    #[test]
+    fn test_big_endian_keystream() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let mut little = [0u8; 128];
+        ChaCha20::new(key, nonce, 64).apply_keystream(&mut little);
+        let mut big = [0u8; 128];
+        ChaCha20::new_be(key, nonce, 64).apply_keystream(&mut big);
+        for (le, be) in little.chunks(4).zip(big.chunks(4)) {
+            assert_eq!(le, [be[3], be[2], be[1], be[0]]);
+        }
+        assert_ne!(
+            ChaCha20::new(key, nonce, 64),
+            ChaCha20::new_be(key, nonce, 64)
+        );
+    }
+
+    #[test]
+    fn test_apply_keystream_vectored() {
+        let key = hex::decode("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f")
+            .unwrap();
+        let key: [u8; 32] = key.try_into().unwrap();
+        let nonce = hex::decode("000000000000004a00000000").unwrap();
+        let nonce: [u8; 12] = nonce.try_into().unwrap();
+        let plaintext = *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let mut joined = plaintext;
+        let mut chacha = ChaCha20::new(key, nonce, 64);
+        chacha.apply_keystream(&mut joined);
+        let mut pieces = plaintext;
+        let (header, body) = pieces.split_at_mut(3);
+        let (body, trailer) = body.split_at_mut(80);
+        let mut vectored = ChaCha20::new(key, nonce, 64);
+        vectored.apply_keystream_vectored(&mut [header, &mut [], body, trailer]);
+        assert_eq!(header, &joined[..3]);
+        assert_eq!(body, &joined[3..83]);
+        assert_eq!(trailer, &joined[83..]);
+        assert_eq!(vectored, chacha);
+    }
+
+    /// Seed for the randomized tests, fixed so any failure can be reproduced.
+    const FUZZ_SEED: u64 = 0x6368_6163_6861_3230;
+
+    fn gen_garbage(rng: &mut StdRng, garbage_len: u32) -> Vec<u8> {
+        let buffer: Vec<u8> = (0..garbage_len).map(|_| rng.gen()).collect();
+        buffer
+    }
+
+    #[test]
+    fn test_fuzz_other() {
+        let mut rng = StdRng::seed_from_u64(FUZZ_SEED);
+        for round in 0..100 {
+            let garbage_key = gen_garbage(&mut rng, 32);
+            let key: [u8; 32] = garbage_key.as_slice().try_into().unwrap();
+            let garbage_nonce = gen_garbage(&mut rng, 12);
+            let nonce: [u8; 12] = garbage_nonce.as_slice().try_into().unwrap();
+            for i in 0..10 {
+                let count: u64 = i * 11;
+                let mut chacha = ChaCha20::new(key, nonce, count);
+                let message = gen_garbage(&mut rng, 129);
+                let mut message2 = message.clone();
+                let msg = message2.as_mut_slice();
+                chacha.apply_keystream(msg);
+                let mut cipher = rustcrypto_chacha20::ChaCha20::new_from_slices(&key, &nonce)
+                    .expect("Valid keys and nonce.");
+                let mut buffer = message;
+                cipher.seek(count);
+                cipher.apply_keystream(&mut buffer);
+                assert_eq!(
+                    buffer.as_slice(),
+                    msg,
+                    "seed {:#x} round {} count {}",
+                    FUZZ_SEED,
+                    round,
+                    count
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "cipher-traits")]
+    fn cipher_traits_apply<C: KeyIvInit + StreamCipher + StreamCipherSeek>(
+        key: &[u8],
+        nonce: &[u8],
+        count: u64,
+        buffer: &mut [u8],
+    ) -> u64 {
+        let mut cipher = C::new_from_slices(key, nonce).expect("Valid keys and nonce.");
+        cipher.seek(count);
+        cipher.apply_keystream(buffer);
+        cipher.current_pos()
+    }
+
+    #[test]
+    #[cfg(feature = "cipher-traits")]
+    fn test_fuzz_cipher_traits() {
+        let mut rng = StdRng::seed_from_u64(FUZZ_SEED);
+        for _ in 0..100 {
+            let key = gen_garbage(&mut rng, 32);
+            let nonce = gen_garbage(&mut rng, 12);
+            let count = rng.gen_range(0..1 << 20);
+            let len = rng.gen_range(0..300);
+            let message = gen_garbage(&mut rng, len);
+            let mut ours = message.clone();
+            let mut theirs = message;
+            let our_pos = cipher_traits_apply::<ChaCha20>(&key, &nonce, count, &mut ours);
+            let their_pos = cipher_traits_apply::<rustcrypto_chacha20::ChaCha20>(
+                &key,
+                &nonce,
+                count,
+                &mut theirs,
+            );
+            assert_eq!(ours, theirs);
+            assert_eq!(our_pos, their_pos);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "cipher-traits")]
+    fn test_cipher_traits_end_of_keystream() {
+        let mut chacha = ChaCha20::new([0x42u8; 32], [0x24u8; 12], KEYSTREAM_LEN - 16);
+        let mut buffer = [0u8; 17];
+        assert!(chacha.try_apply_keystream(&mut buffer).is_err());
+        assert_eq!(buffer, [0u8; 17]);
+        assert!(chacha.try_apply_keystream(&mut buffer[..16]).is_ok());
+        assert!(chacha.try_seek(KEYSTREAM_LEN + 1).is_err());
+        assert!(chacha.try_seek(KEYSTREAM_LEN).is_ok());
+        assert!(chacha.is_exhausted());
+    }
+
+    #[test]
+    fn test_cached_keystream_matches_uncached() {
+        let mut rng = StdRng::seed_from_u64(FUZZ_SEED);
+        for _ in 0..64 {
+            let key: [u8; 32] = rng.gen();
+            let nonce: [u8; 12] = rng.gen();
+            let start = rng.gen_range(0..1 << 20);
+            let len = rng.gen_range(0..300);
+            let mut expected = vec![0u8; len];
+            for (i, chunk) in expected.chunks_mut(CHACHA_BLOCKSIZE).enumerate() {
+                let position = start + (i * CHACHA_BLOCKSIZE) as u64;
+                let kstream = cipher::keystream_at_slice(
+                    key,
+                    nonce,
+                    (position / 64) as u32,
+                    (position % 64) as usize,
+                    ByteOrder::Little,
+                );
+                chunk.copy_from_slice(&kstream[..chunk.len()]);
+            }
+            let mut stream = KeyStream::new(key, nonce, start);
+            let mut bytewise = vec![0u8; len];
+            for byte in bytewise.iter_mut() {
+                stream.fill(::core::slice::from_mut(byte));
+            }
+            assert_eq!(bytewise, expected);
+            let mut chacha = ChaCha20::new(key, nonce, start);
+            let mut mixed = vec![0u8; len];
+            let mut rest = mixed.as_mut_slice();
+            while !rest.is_empty() {
+                let (chunk, tail) = rest.split_at_mut(rng.gen_range(1..=rest.len().min(70)));
+                chacha.apply_keystream(chunk);
+                rest = tail;
+            }
+            assert_eq!(mixed, expected);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "profiling", not(feature = "constant-path")))]
+    fn test_bytewise_reads_use_cache() {
+        let mut chacha = ChaCha20::new([0x42u8; 32], [0x24u8; 12], 10);
+        for _ in 0..128 {
+            chacha.apply_keystream(&mut [0u8; 1]);
+        }
+        assert_eq!(chacha.block_computations(), 3);
+    }
+
+    #[test]
+    fn test_random_round_trip() {
+        let mut rng = StdRng::seed_from_u64(FUZZ_SEED);
+        for _ in 0..256 {
+            let key: [u8; 32] = rng.gen();
+            let nonce: [u8; 12] = rng.gen();
+            let len = rng.gen_range(0..1024);
+            let seek = rng.gen_range(0..(u32::MAX as u64 - 16) * 64);
+            let message = gen_garbage(&mut rng, len);
+            let mut ours = message.clone();
+            ChaCha20::new(key, nonce, seek).apply_keystream(&mut ours);
+            let mut theirs = message.clone();
+            let mut cipher = rustcrypto_chacha20::ChaCha20::new(&key.into(), &nonce.into());
+            cipher.seek(seek);
+            cipher.apply_keystream(&mut theirs);
+            assert_eq!(
+                ours, theirs,
+                "seed {:#x} seek {} len {}",
+                FUZZ_SEED, seek, len
+            );
+            ChaCha20::new(key, nonce, seek).apply_keystream(&mut ours);
+            assert_eq!(
+                ours, message,
+                "seed {:#x} seek {} len {}",
+                FUZZ_SEED, seek, len
+            );
         }
     }
 }
@@ -0,0 +1,162 @@
+//! Exercises the public API under whichever features are enabled, so feature combinations keep building.
+//!
+//! Run with `cargo test --all-features` as well as the default features.
+
+use chacha20::{ChaCha20, ChaChaError, KeyStream};
+
+const KEY: [u8; 32] = [0x42; 32];
+const NONCE: [u8; 12] = [0x24; 12];
+const MESSAGE: &[u8] = b"Ladies and Gentlemen of the class of '99";
+
+#[test]
+fn construction_and_round_trip() {
+    let mut ciphertext = MESSAGE.to_vec();
+    ChaCha20::new(KEY, NONCE, 7).apply_keystream(&mut ciphertext);
+    assert_ne!(ciphertext, MESSAGE);
+    ChaCha20::new(KEY, NONCE, 7).decrypt(&mut ciphertext);
+    assert_eq!(ciphertext, MESSAGE);
+
+    let mut output = vec![0u8; MESSAGE.len()];
+    ChaCha20::new_from_block(KEY, NONCE, 1)
+        .encrypt(MESSAGE, &mut output)
+        .unwrap();
+    let mut keystream = vec![0u8; MESSAGE.len()];
+    KeyStream::new(KEY, NONCE, 64).fill(&mut keystream);
+    let xored: Vec<u8> = MESSAGE.iter().zip(&keystream).map(|(m, k)| m ^ k).collect();
+    assert_eq!(output, xored);
+    assert_eq!(
+        ChaCha20::new(KEY, NONCE, 0).encrypt(MESSAGE, &mut [0u8; 3]),
+        Err(ChaChaError::LengthMismatch)
+    );
+}
+
+#[test]
+#[cfg(feature = "alloc")]
+fn alloc_round_trip() {
+    let ciphertext = ChaCha20::new(KEY, NONCE, 7).encrypt_to_vec(MESSAGE);
+    let plaintext = ChaCha20::new(KEY, NONCE, 7).encrypt_to_vec(&ciphertext);
+    assert_eq!(plaintext, MESSAGE);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn std_error() {
+    let error: Box<dyn std::error::Error> = Box::new(ChaChaError::LengthMismatch);
+    assert_eq!(error.to_string(), "input and output lengths differ");
+    let error: Box<dyn std::error::Error> = Box::new(chacha20::WeakInput::ZeroKey);
+    assert_eq!(error.to_string(), "key is all zeros");
+}
+
+#[test]
+#[cfg(feature = "getrandom")]
+fn getrandom_round_trip() {
+    let nonce = chacha20::random_nonce().unwrap();
+    let mut ciphertext = MESSAGE.to_vec();
+    ChaCha20::new(KEY, nonce, 0).apply_keystream(&mut ciphertext);
+    ChaCha20::new(KEY, nonce, 0).apply_keystream(&mut ciphertext);
+    assert_eq!(ciphertext, MESSAGE);
+}
+
+#[test]
+#[cfg(feature = "profiling")]
+fn profiling_round_trip() {
+    let mut chacha = ChaCha20::new(KEY, NONCE, 0);
+    let mut ciphertext = MESSAGE.to_vec();
+    chacha.apply_keystream(&mut ciphertext);
+    assert!(chacha.block_computations() > 0);
+    ChaCha20::new(KEY, NONCE, 0).apply_keystream(&mut ciphertext);
+    assert_eq!(ciphertext, MESSAGE);
+}
+
+#[test]
+#[cfg(feature = "constant-path")]
+fn constant_path_round_trip() {
+    for seek in [0, 1, 63, 64, 200] {
+        let mut ciphertext = MESSAGE.to_vec();
+        ChaCha20::new(KEY, NONCE, seek).apply_keystream(&mut ciphertext);
+        let mut keystream = vec![0u8; MESSAGE.len()];
+        KeyStream::new(KEY, NONCE, seek).fill(&mut keystream);
+        let xored: Vec<u8> = MESSAGE.iter().zip(&keystream).map(|(m, k)| m ^ k).collect();
+        assert_eq!(ciphertext, xored);
+        ChaCha20::new(KEY, NONCE, seek).decrypt(&mut ciphertext);
+        assert_eq!(ciphertext, MESSAGE);
+    }
+}
+
+#[test]
+#[cfg(feature = "cipher-traits")]
+fn cipher_traits_round_trip() {
+    use cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+    let mut chacha = <ChaCha20 as KeyIvInit>::new(&KEY.into(), &NONCE.into());
+    StreamCipherSeek::seek(&mut chacha, 7u64);
+    let mut ciphertext = MESSAGE.to_vec();
+    StreamCipher::apply_keystream(&mut chacha, &mut ciphertext);
+    assert_eq!(chacha.current_pos::<u64>(), 7 + MESSAGE.len() as u64);
+    let mut expected = MESSAGE.to_vec();
+    ChaCha20::new(KEY, NONCE, 7).apply_keystream(&mut expected);
+    assert_eq!(ciphertext, expected);
+    let mut decryptor = ChaCha20::new_from_slices(&KEY, &NONCE).unwrap();
+    StreamCipherSeek::seek(&mut decryptor, 7u64);
+    StreamCipher::apply_keystream(&mut decryptor, &mut ciphertext);
+    assert_eq!(ciphertext, MESSAGE);
+}
+
+#[test]
+#[cfg(feature = "debug_guards")]
+#[should_panic(expected = "Keystream reused")]
+fn debug_guards_reject_reuse() {
+    let mut chacha = ChaCha20::new(KEY, NONCE, 0);
+    chacha.apply_keystream(&mut [0u8; 10]);
+    chacha.seek(0);
+    chacha.apply_keystream(&mut [0u8; 10]);
+}
+
+#[test]
+#[cfg(feature = "ffi")]
+#[allow(unsafe_code)]
+fn ffi_round_trip() {
+    use chacha20::ffi::*;
+    let mut buffer = MESSAGE.to_vec();
+    unsafe {
+        for _ in 0..2 {
+            let state = chacha20_new(KEY.as_ptr(), NONCE.as_ptr(), 7);
+            assert!(!state.is_null());
+            assert_eq!(
+                chacha20_apply(state, buffer.as_mut_ptr(), buffer.len()),
+                CHACHA20_OK
+            );
+            chacha20_free(state);
+        }
+    }
+    assert_eq!(buffer, MESSAGE);
+}
+
+#[test]
+#[cfg(all(feature = "ffi", feature = "debug_guards"))]
+#[allow(unsafe_code)]
+fn ffi_reports_reuse_without_panicking() {
+    use chacha20::ffi::*;
+    let mut buffer = MESSAGE.to_vec();
+    let original = buffer.clone();
+    unsafe {
+        let state = chacha20_new(KEY.as_ptr(), NONCE.as_ptr(), 0);
+        assert_eq!(
+            chacha20_apply(state, buffer.as_mut_ptr(), buffer.len()),
+            CHACHA20_OK
+        );
+        let ciphertext = buffer.clone();
+        assert_eq!(chacha20_seek(state, 0), CHACHA20_OK);
+        assert_eq!(
+            chacha20_apply(state, buffer.as_mut_ptr(), buffer.len()),
+            CHACHA20_ERR_REUSE
+        );
+        assert_eq!(buffer, ciphertext);
+        assert_ne!(buffer, original);
+        assert_eq!(chacha20_seek(state, 1000), CHACHA20_OK);
+        assert_eq!(
+            chacha20_apply(state, buffer.as_mut_ptr(), buffer.len()),
+            CHACHA20_OK
+        );
+        chacha20_free(state);
+    }
+}